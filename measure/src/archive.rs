@@ -0,0 +1,240 @@
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::WrapErr;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    Benchmark, BenchmarkStats, Endpoint, RESULTS_TARGET,
+    stats::{Recorder, StageStats, format_latency, is_success},
+};
+
+/* Per-request archive */
+
+/// Outcome of a single request. Serialized as a MessagePack array, so that archives with millions
+/// of requests stay small.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct RequestRecord {
+    /// When the request started, in microseconds since the start of the run.
+    started_us: u64,
+    /// In nanoseconds, or microseconds in archives without `latency_ns`.
+    latency: u64,
+    /// HTTP status, or `None` if the request failed without a valid response.
+    status: Option<u16>,
+    /// Index into the archive's targets.
+    target: u16,
+    /// Index into the archive's endpoints.
+    endpoint: u8,
+}
+
+/// Every request made during a run, along with the run's parameters, for `analyze` to recompute
+/// its summary.
+#[derive(Debug, Serialize, Deserialize)]
+struct Archive {
+    targets: Vec<String>,
+    endpoints: Vec<Endpoint>,
+    concurrency: usize,
+    size: usize,
+    elapsed_us: u64,
+    /// Whether error statuses counted as failures during the run.
+    error_for_status: bool,
+    records: Vec<RequestRecord>,
+    /// Whether the latencies are in nanoseconds. Older archives only had microseconds.
+    #[serde(default)]
+    latency_ns: bool,
+    /// Payload bytes transferred by successful requests. Older archives didn't record it.
+    #[serde(default)]
+    bytes: Option<u64>,
+}
+
+/// Collects the outcome of every request while running.
+pub(crate) struct Records {
+    started: Instant,
+    records: Mutex<Vec<RequestRecord>>,
+}
+
+impl Records {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            records: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a completed request by the index of its target and endpoint, with its status if it
+    /// received a valid response.
+    pub(crate) fn record(
+        &self,
+        started: Instant,
+        latency: Duration,
+        status: Option<StatusCode>,
+        target: usize,
+        endpoint: usize,
+    ) {
+        let record = RequestRecord {
+            started_us: micros(started.saturating_duration_since(self.started)),
+            latency: nanos(latency),
+            status: status.map(|status| status.as_u16()),
+            target: target as u16,
+            endpoint: endpoint as u8,
+        };
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// Serializes the records collected so far as MessagePack, along with the run's parameters.
+    pub(crate) fn to_msgpack(
+        &self,
+        targets: &[(&str, u32)],
+        endpoints: Vec<Endpoint>,
+        stats: &StageStats,
+        size: usize,
+        bytes: u64,
+        error_for_status: bool,
+    ) -> color_eyre::Result<Vec<u8>> {
+        let archive = Archive {
+            targets: targets
+                .iter()
+                .map(|(base_url, _)| base_url.to_string())
+                .collect(),
+            endpoints,
+            concurrency: stats.concurrency,
+            size,
+            elapsed_us: micros(stats.elapsed),
+            error_for_status,
+            records: std::mem::take(&mut *self.records.lock().unwrap()),
+            latency_ns: true,
+            bytes: Some(bytes),
+        };
+        Ok(rmp_serde::to_vec(&archive)?)
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Loads the records written by a previous run, and logs its summary recomputed from every
+/// request, overall and for each target and endpoint.
+pub fn analyze(path: &Path) -> color_eyre::Result<BenchmarkStats> {
+    let archive: Archive = rmp_serde::from_slice(
+        &std::fs::read(path)
+            .wrap_err_with(|| format!("Unable to read records {}.", path.display()))?,
+    )
+    .wrap_err_with(|| format!("Invalid records {}.", path.display()))?;
+    // Older archives didn't record the bytes, so they're estimated from the size instead.
+    let bytes = archive.bytes.unwrap_or_else(|| {
+        archive
+            .records
+            .iter()
+            .filter(|record| {
+                record
+                    .status
+                    .and_then(|status| StatusCode::from_u16(status).ok())
+                    .is_some_and(|status| !archive.error_for_status || is_success(status))
+            })
+            .filter_map(|record| archive.endpoints.get(usize::from(record.endpoint)))
+            .map(|endpoint| Benchmark::payload_bytes(*endpoint, archive.size))
+            .sum()
+    });
+    let mut total = Recorder::default();
+    let mut target_recorders: Vec<Recorder> = archive
+        .targets
+        .iter()
+        .map(|_| Recorder::default())
+        .collect();
+    let mut endpoint_recorders: Vec<Recorder> = archive
+        .endpoints
+        .iter()
+        .map(|_| Recorder::default())
+        .collect();
+    for record in archive.records.iter() {
+        let latency = if archive.latency_ns {
+            Duration::from_nanos(record.latency)
+        } else {
+            Duration::from_micros(record.latency)
+        };
+        let status = record
+            .status
+            .and_then(|status| StatusCode::from_u16(status).ok());
+        let recorders = [
+            Some(&mut total),
+            target_recorders.get_mut(usize::from(record.target)),
+            endpoint_recorders.get_mut(usize::from(record.endpoint)),
+        ];
+        for recorder in recorders.into_iter().flatten() {
+            match status {
+                Some(status) if archive.error_for_status => recorder.record_status(status, latency),
+                Some(status) => recorder.record_accepted_status(status, latency),
+                None => recorder.record_connection_error(),
+            }
+        }
+    }
+    let concurrency = archive.concurrency;
+    let elapsed = Duration::from_micros(archive.elapsed_us);
+    let mut stats = StageStats::new(concurrency, elapsed, total);
+    if target_recorders.len() > 1 {
+        stats.targets = archive
+            .targets
+            .into_iter()
+            .zip(target_recorders)
+            .map(|(target, recorder)| (target, StageStats::new(concurrency, elapsed, recorder)))
+            .collect();
+    }
+    if endpoint_recorders.len() > 1 {
+        stats.endpoints = archive
+            .endpoints
+            .into_iter()
+            .zip(endpoint_recorders)
+            .map(|(endpoint, recorder)| (endpoint, StageStats::new(concurrency, elapsed, recorder)))
+            .collect();
+    }
+    info!(
+        target: RESULTS_TARGET,
+        path = %path.display(),
+        concurrency,
+        size = archive.size,
+        total_requests = stats.requests(),
+        failures = stats.failures(),
+        connection_errors = stats.connection_errors,
+        statuses = ?stats.statuses,
+        rps = format!("{:.2}", stats.rps()),
+        p50 = format_latency(stats.percentile(50.0)),
+        p90 = format_latency(stats.percentile(90.0)),
+        p99 = format_latency(stats.percentile(99.0)),
+        p999 = format_latency(stats.percentile(99.9)),
+        elapsed = humantime::format_duration(elapsed).to_string(),
+        "Records analyzed."
+    );
+    for (target, target_stats) in stats.targets.iter() {
+        info!(
+            %target,
+            total_requests = target_stats.requests(),
+            failures = target_stats.failures(),
+            rps = format!("{:.2}", target_stats.rps()),
+            p50 = format_latency(target_stats.percentile(50.0)),
+            p99 = format_latency(target_stats.percentile(99.0)),
+            "Target analyzed."
+        );
+    }
+    for (endpoint, endpoint_stats) in stats.endpoints.iter() {
+        info!(
+            %endpoint,
+            total_requests = endpoint_stats.requests(),
+            failures = endpoint_stats.failures(),
+            rps = format!("{:.2}", endpoint_stats.rps()),
+            p50 = format_latency(endpoint_stats.percentile(50.0)),
+            p99 = format_latency(endpoint_stats.percentile(99.0)),
+            "Endpoint analyzed."
+        );
+    }
+    Ok(BenchmarkStats::new(stats, bytes))
+}
@@ -1,20 +1,91 @@
-use std::{fmt::Display, net::SocketAddr, path::PathBuf, sync::Arc, time::Instant};
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use bytes::Bytes;
+use backon::{ExponentialBuilder, Retryable};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Bytes, BytesMut};
+use color_eyre::eyre::{WrapErr, eyre};
+use flate2::read::{DeflateDecoder, GzDecoder};
 use futures::{SinkExt, TryStreamExt, future::try_join_all};
-use rand::RngCore;
-use reqwest_websocket::RequestBuilderExt;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use reqwest::{
+    StatusCode,
+    header::{
+        AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, TE,
+        USER_AGENT,
+    },
+    multipart::{Form, Part},
+};
+use reqwest_websocket::{RequestBuilderExt, WebSocket};
 use rustls::{
     ClientConfig, RootCertStore,
-    pki_types::{CertificateDer, pem::PemObject},
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::{debug, error, info, instrument, warn};
+use uuid::Uuid;
+
+mod archive;
+mod probe;
+mod replay;
+mod report;
+mod stats;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod throttle;
+
+pub use sandhole_benchmark_payload::PayloadPattern;
+
+#[cfg(feature = "otel")]
+pub use crate::telemetry::Otlp;
+use crate::{
+    archive::Records,
+    probe::Probe,
+    replay::{Replay, parse_replay},
+    report::{Report, percent_change},
+    stats::{
+        InvalidResponse, Recorder, SlidingWindow, Snapshot, Timeseries, WebsocketClosed,
+        format_latency, is_success,
+    },
+    throttle::{Bandwidth, throttled_body, throttled_read},
 };
-use tracing::{info, instrument};
+pub use crate::{
+    archive::analyze,
+    stats::{BenchmarkStats, SseStats, StageStats},
+};
+
+/// Tracing target of the final results line.
+pub const RESULTS_TARGET: &str = "sandhole_benchmark_measure::results";
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
 pub enum Endpoint {
     Get,
     Post,
     Websocket,
+    Sse,
+    /// Freshly generated random bytes, without the GET endpoint's shared buffer.
+    Bytes,
+    /// GET data that the service always gzip-encodes, decoded and checked by the client.
+    Gzip,
+    /// GET data that the service always deflate-encodes, decoded and checked by the client.
+    Deflate,
+    /// Chunked GET data followed by a checksum trailer, checked by the client.
+    Trailers,
+    /// A fixed `pong` body regardless of `--size`, for measuring the latency of the tunnel itself.
+    Ping,
 }
 
 impl Display for Endpoint {
@@ -23,154 +94,2720 @@ impl Display for Endpoint {
             Endpoint::Get => "GET",
             Endpoint::Post => "POST",
             Endpoint::Websocket => "WebSocket",
+            Endpoint::Sse => "SSE",
+            Endpoint::Bytes => "bytes",
+            Endpoint::Gzip => "gzip",
+            Endpoint::Deflate => "deflate",
+            Endpoint::Trailers => "trailers",
+            Endpoint::Ping => "ping",
         })
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
 pub struct EntrypointConfig {
-    pub base_url: String,
+    pub targets: Vec<Target>,
+    /// Address of the service to benchmark over plain HTTP, bypassing the tunnel. Replaces
+    /// `targets` when set.
+    pub direct: Option<String>,
+    /// Unix socket of the service to benchmark over plain HTTP, bypassing both TCP and the tunnel.
+    /// Replaces `targets` when set.
+    pub uds: Option<PathBuf>,
     pub endpoint: Endpoint,
+    /// Concurrency for each endpoint of a mixed workload, with a separate pool of workers each.
+    /// Overrides `endpoint` and `concurrency` when not empty.
+    pub mix: Vec<(Endpoint, usize)>,
+    /// Path to request instead of the endpoint's own, where `{size}` is replaced by the size.
+    pub path: Option<String>,
     pub size: usize,
     pub concurrency: usize,
+    pub requests: usize,
+    /// How long each worker keeps sending requests. Overrides `requests` when set.
+    pub duration: Option<Duration>,
+    /// Target requests per second across all workers. Latencies are measured from each request's
+    /// scheduled send time when set.
+    pub rate: Option<f64>,
+    /// New connections to open per second across all workers, each carrying a single request.
+    pub connections_per_second: Option<f64>,
+    /// Maximum number of requests in flight across all workers.
+    pub max_inflight: Option<usize>,
+    /// Random pause between each worker's requests, not counted toward latency.
+    pub think_time: Option<ThinkTime>,
+    pub keepalive: bool,
+    /// Number of independent clients that the workers are spread across, each with its own
+    /// connection pool. With HTTP/2, each client multiplexes its workers' requests as concurrent
+    /// streams over a single connection.
+    pub connections: usize,
+    /// Whether to use HTTP/2 without negotiating it first.
+    pub http2: bool,
+    /// Whether to use HTTP/3 over QUIC without negotiating it first. Only available with the
+    /// `http3` feature.
+    pub http3: bool,
+    pub socket: SocketConfig,
+    /// Maximum upload rate for each POST body, in bytes per second.
+    pub max_upload_bps: Option<u64>,
+    /// Maximum download rate for each GET body, in bytes per second.
+    pub max_download_bps: Option<u64>,
+    /// Largest response body to read, in bytes, before failing the request instead of buffering
+    /// the rest.
+    pub max_response_size: Option<u64>,
+    /// Whether to keep cookies set by responses and send them on later requests.
+    pub cookies: bool,
+    pub body_file: Option<PathBuf>,
+    /// Content of the generated POST/WebSocket body, and of the service's GET data when verifying
+    /// it.
+    pub payload_pattern: PayloadPattern,
+    pub sse_events: usize,
+    /// Whether each WebSocket worker keeps a single connection open for the whole run.
+    pub websocket_persistent: bool,
+    /// How many times to retry a WebSocket upgrade that fails before completing the handshake.
+    pub websocket_retries: usize,
+    /// Whether POST requests use the endpoint that reports received and expected sizes.
+    pub post_info: bool,
+    /// Whether POST requests upload the body as a multipart form instead.
+    pub multipart: bool,
+    /// Whether to time the DNS, connect, TLS and time-to-first-byte phases of each request, using
+    /// a fresh connection per request.
+    pub timing_breakdown: bool,
+    /// Whether to only open and close a fresh TLS connection for each request, timing the
+    /// handshake without any HTTP request.
+    pub handshake_only: bool,
+    /// Whether POST requests send `Expect: 100-continue` and wait for the 100 Continue response
+    /// before sending the body, over fresh connections as with `timing_breakdown`.
+    pub expect_continue: bool,
+    /// Number of GET requests that each worker writes over a fresh connection before reading any
+    /// response, to test HTTP/1.1 pipelining.
+    pub pipeline: Option<usize>,
     pub custom_ca_cert: Option<PathBuf>,
+    /// PEM certificate chain to present for mutual TLS, along with `client_key`.
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate.
+    pub client_key: Option<PathBuf>,
     pub host_ip: Option<SocketAddr>,
+    pub resolve: Vec<ResolveOverride>,
+    pub proxy: Option<String>,
+    /// Extra headers sent with every request, including WebSocket upgrades.
+    pub headers: Vec<Header>,
+    /// `User-Agent` header, unless one is set in `headers`.
+    pub user_agent: String,
+    /// Credentials for the `Authorization` header, replacing any set in `headers`.
+    pub auth: Option<Auth>,
+    pub autoscale: Option<AutoscaleConfig>,
+    pub hol_blocking: Option<HolBlockingConfig>,
+    /// Recorded request log to replay instead of the endpoint's requests.
+    pub replay: Option<ReplayConfig>,
+    pub sla: SlaConfig,
+    pub abort: Option<AbortConfig>,
+    /// File to write the results to as JSON.
+    pub json: Option<PathBuf>,
+    /// File to write the results to in the Prometheus text exposition format.
+    pub prom_file: Option<PathBuf>,
+    /// File to write per-second results to as CSV.
+    pub timeseries: Option<PathBuf>,
+    /// File to write every request's outcome to as MessagePack, for `analyze`.
+    pub records_file: Option<PathBuf>,
+    /// How often to log a summary of the requests completed since the last one.
+    pub snapshot_interval: Option<Duration>,
+    pub baseline: Option<BaselineConfig>,
+    /// Payload bytes sent and received across all workers after which the run stops.
+    pub max_bytes: Option<u64>,
+    /// Whether to stop the run with an error on the first failed request, instead of tallying
+    /// failures.
+    pub fail_fast: bool,
+    /// Whether 4xx and 5xx responses count as failures. Otherwise, they're only told apart in the
+    /// status distribution, and their latencies are recorded like any other response's.
+    pub error_for_status: bool,
+    /// How long after a detected tunnel reconnect requests are tagged as recovery, to summarize
+    /// them apart from the steady state.
+    pub recovery_window: Duration,
+    /// Whether to round-trip one GET, POST and WebSocket exchange with each target before the run.
+    pub selftest: bool,
+    /// Wall-clock time to start the load at, so that several instances can run in sync.
+    pub start_at: Option<SystemTime>,
+    /// Seed used by the service to generate its GET data.
+    pub seed: Option<u64>,
+    /// Content type that GET responses must have, to detect rewritten headers.
+    pub expect_content_type: Option<String>,
+    /// Whether to verify GET bodies against the data generated from the seed.
+    pub verify: bool,
+    /// Whether to also log the effective configuration as JSON at startup.
+    pub config_json: bool,
+}
+
+/// A URL to benchmark, picked for each request in proportion to its weight. Parsed from either
+/// `url` or `url=weight`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Target {
+    pub url: String,
+    pub weight: u32,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value
+            .rsplit_once('=')
+            .and_then(|(url, weight)| weight.parse::<u32>().ok().map(|weight| (url, weight)))
+        {
+            Some((_, 0)) => Err("weight must be positive".to_string()),
+            Some((url, weight)) => Ok(Self {
+                url: url.to_string(),
+                weight,
+            }),
+            None => Ok(Self {
+                url: value.to_string(),
+                weight: 1,
+            }),
+        }
+    }
+}
+
+/// Bounds for the uniformly random pause between requests. Parsed from `min:max`, with
+/// human-readable durations.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ThinkTime {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl FromStr for ThinkTime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (min, max) = value
+            .split_once(':')
+            .ok_or_else(|| "expected min:max".to_string())?;
+        let min = humantime::parse_duration(min).map_err(|error| error.to_string())?;
+        let max = humantime::parse_duration(max).map_err(|error| error.to_string())?;
+        if min > max {
+            return Err("min must not be greater than max".to_string());
+        }
+        Ok(Self { min, max })
+    }
+}
+
+/// A header sent with every request. Parsed from `name: value`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for Header {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, value) = value
+            .split_once(':')
+            .ok_or_else(|| "expected name: value".to_string())?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+}
+
+/// Credentials sent in the `Authorization` header. Secrets are redacted when logged.
+#[derive(Clone, Serialize)]
+pub enum Auth {
+    Basic {
+        user: String,
+        #[serde(serialize_with = "serialize_redacted")]
+        password: String,
+    },
+    Bearer(#[serde(serialize_with = "serialize_redacted")] String),
+}
+
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::Basic { user, .. } => f
+                .debug_struct("Basic")
+                .field("user", user)
+                .field("password", &REDACTED)
+                .finish(),
+            Auth::Bearer(_) => f.debug_tuple("Bearer").field(&REDACTED).finish(),
+        }
+    }
+}
+
+/// Placeholder for secrets in logged configuration.
+const REDACTED: &str = "<redacted>";
+
+fn serialize_redacted<S: serde::Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+impl Auth {
+    fn header_value(&self) -> color_eyre::Result<HeaderValue> {
+        let value = match self {
+            Auth::Basic { user, password } => {
+                format!("Basic {}", STANDARD.encode(format!("{user}:{password}")))
+            }
+            Auth::Bearer(token) => format!("Bearer {token}"),
+        };
+        let mut value = HeaderValue::try_from(value).wrap_err_with(|| "Invalid credentials.")?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+/// Options for the client's TCP sockets. These only affect the connection from the client to the
+/// tunnel's entrypoint, not the SSH connection behind it.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SocketConfig {
+    /// Whether to disable Nagle's algorithm, as the service does for its SSH connection.
+    pub nodelay: bool,
+    /// Local IP address to bind outgoing connections to, to pick the network interface.
+    pub local_address: Option<IpAddr>,
+}
+
+/// A hostname pinned to a specific address, in the same `host:port:addr` format as curl's
+/// `--resolve`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.splitn(3, ':');
+        let (Some(host), Some(port), Some(addr)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected host:port:addr".to_string());
+        };
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| "invalid port".to_string())?;
+        let addr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map_err(|_| "invalid address".to_string())?;
+        Ok(Self {
+            host: host.to_string(),
+            addr: SocketAddr::new(addr, port),
+        })
+    }
+}
+
+/// Large transfers to run alongside the requests, to measure head-of-line blocking on shared
+/// HTTP/2 connections.
+#[derive(Debug, Clone, Serialize)]
+pub struct HolBlockingConfig {
+    /// Size of each large GET transfer.
+    pub large_size: usize,
+    /// Number of large transfers in flight at once.
+    pub large_concurrency: usize,
+}
+
+/// Configuration for finding the maximum sustainable concurrency.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoscaleConfig {
+    /// Highest concurrency to try.
+    pub max_concurrency: usize,
+    /// How many times the baseline p99 latency is tolerated before stopping.
+    pub threshold: f64,
+}
+
+/// Stops the benchmark early when the p99 latency over a sliding window exceeds a limit.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbortConfig {
+    pub max_p99: Duration,
+    pub window: Duration,
+}
+
+/// Thresholds that the benchmark results must meet, making the run fail otherwise.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SlaConfig {
+    pub max_p99: Option<Duration>,
+    /// Maximum error rate, from 0 to 100.
+    pub max_error_rate: Option<f64>,
+    pub min_rps: Option<f64>,
+}
+
+impl SlaConfig {
+    /// Returns a description of each threshold that the stats fail to meet.
+    fn violations(&self, stats: &StageStats) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Some(max_p99) = self.max_p99 {
+            let p99 = stats.percentile(99.0);
+            if p99 > max_p99 {
+                violations.push(format!(
+                    "p99 latency {} exceeds maximum of {}",
+                    format_latency(p99),
+                    format_latency(max_p99)
+                ));
+            }
+        }
+        if let Some(max_error_rate) = self.max_error_rate {
+            let error_rate = stats.error_rate() * 100.0;
+            if error_rate > max_error_rate {
+                violations.push(format!(
+                    "error rate {error_rate:.2}% exceeds maximum of {max_error_rate:.2}%"
+                ));
+            }
+        }
+        if let Some(min_rps) = self.min_rps {
+            let rps = stats.rps();
+            if rps < min_rps {
+                violations.push(format!("{rps:.2} RPS is below minimum of {min_rps:.2}"));
+            }
+        }
+        violations
+    }
+}
+
+/// A request log with `timestamp,method,path,size` rows, replayed with the recorded timing.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    /// Factor to speed up the recorded timing by.
+    pub speed: f64,
+}
+
+/// A previous run's JSON results to compare against.
+#[derive(Debug, Clone, Serialize)]
+pub struct BaselineConfig {
+    pub path: PathBuf,
+    /// Allowed regression, in percent for RPS and p99 latency, or in percentage points for the
+    /// error rate.
+    pub tolerance: f64,
+}
+
+/// State shared by all workers of a benchmark.
+struct Benchmark {
+    /// Base URLs without a scheme, along with their weights.
+    targets: Vec<(&'static str, u32)>,
+    /// Whether to connect over TLS, or over plain HTTP when benchmarking the service directly.
+    secure: bool,
+    /// Counter for round-robin selection when all weights are equal.
+    next_target: AtomicUsize,
+    /// Clients that the workers are spread across, in round-robin.
+    clients: Vec<reqwest::Client>,
+    endpoint: Endpoint,
+    mix: Vec<(Endpoint, usize)>,
+    path: Option<String>,
+    data: Bytes,
+    size: usize,
+    requests: usize,
+    duration: Option<Duration>,
+    rate: Option<f64>,
+    inflight: Option<Semaphore>,
+    think_time: Option<ThinkTime>,
+    sse_events: usize,
+    websocket_persistent: bool,
+    websocket_retries: usize,
+    /// Number of WebSocket upgrades that were retried after a connection failure.
+    websocket_reconnects: AtomicUsize,
+    post_info: bool,
+    multipart: bool,
+    probe: Option<Probe>,
+    handshake_only: bool,
+    /// Number of requests to pipeline over each of the probe's connections, if any.
+    pipeline: Option<usize>,
+    /// Prefix of the service's GET data, if responses should be verified.
+    expected: Option<Bytes>,
+    expect_content_type: Option<String>,
+    abort: Option<(Duration, SlidingWindow)>,
+    timeseries: Option<Timeseries>,
+    records: Option<Records>,
+    max_upload_bps: Option<u64>,
+    max_download_bps: Option<u64>,
+    max_response_size: Option<u64>,
+    bandwidth: Arc<Bandwidth>,
+    snapshot: Option<(Duration, Snapshot)>,
+    max_bytes: Option<u64>,
+    /// Payload bytes sent and received by successful requests so far.
+    transferred: AtomicU64,
+    fail_fast: bool,
+    error_for_status: bool,
+    /// Last tunnel session that served each target, from the `X-Tunnel-Session` header, or 0.
+    sessions: Vec<AtomicU64>,
+    /// Distinct service connections that served responses, from the `X-Conn-Id` header.
+    connection_ids: Mutex<HashSet<u64>>,
+    /// When the latest tunnel reconnect was detected, if any.
+    reconnected_at: Mutex<Option<Instant>>,
+    recovery_window: Duration,
+    /// Request log whose entries replace the endpoint's requests, if any.
+    replay: Option<Replay>,
+    /// Set when the benchmark must stop early.
+    stop: AtomicBool,
+}
+
+/// Path template, payload and size of a request, from a replayed entry or else the benchmark's
+/// own.
+struct RequestSpec<'a> {
+    path: Option<&'a str>,
+    data: Bytes,
+    size: usize,
+}
+
+impl RequestSpec<'_> {
+    /// Path for the request, from its path template if one is set, or else from the endpoint's own
+    /// template.
+    fn path(&self, default: &str) -> String {
+        self.path
+            .unwrap_or(default)
+            .replace("{size}", &self.size.to_string())
+    }
 }
 
-pub async fn entrypoint(
-    EntrypointConfig {
-        base_url,
+impl Benchmark {
+    /// Whether a response with the given status counts as successful.
+    fn succeeded(&self, status: StatusCode) -> bool {
+        !self.error_for_status || is_success(status)
+    }
+
+    /// Records a response, as a failure if its status is an error that isn't accepted.
+    fn record_response(&self, recorder: &mut Recorder, status: StatusCode, latency: Duration) {
+        if self.error_for_status {
+            recorder.record_status(status, latency);
+        } else {
+            recorder.record_accepted_status(status, latency);
+        }
+    }
+
+    /// Marks a tunnel reconnect as detected now, either from a failed request or a new session.
+    fn detect_reconnect(&self) {
+        *self.reconnected_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Detects a tunnel reconnect from a change in the session serving the target's responses,
+    /// and keeps track of the connection that served the response.
+    fn observe_session(&self, base_url: &str, headers: &HeaderMap) {
+        if let Some(connection) = headers
+            .get("x-conn-id")
+            .and_then(|connection| connection.to_str().ok())
+            .and_then(|connection| connection.parse::<u64>().ok())
+        {
+            debug!(%base_url, connection, "Response served through connection.");
+            self.connection_ids.lock().unwrap().insert(connection);
+        }
+        let Some(session) = headers
+            .get("x-tunnel-session")
+            .and_then(|session| session.to_str().ok())
+            .and_then(|session| session.parse::<u64>().ok())
+        else {
+            return;
+        };
+        let Some(target) = self.targets.iter().position(|(url, _)| *url == base_url) else {
+            return;
+        };
+        let previous = self.sessions[target].swap(session, Ordering::Relaxed);
+        if previous != 0 && previous != session {
+            debug!(previous, session, "Tunnel session changed.");
+            self.detect_reconnect();
+        }
+    }
+
+    /// Whether a request that started at the given instant and just completed overlaps the
+    /// recovery from the latest detected reconnect.
+    fn recovering(&self, started: Instant) -> bool {
+        self.reconnected_at
+            .lock()
+            .unwrap()
+            .is_some_and(|reconnected_at| started <= reconnected_at + self.recovery_window)
+    }
+
+    /// Picks the index of the target for the next request, in round-robin when all weights are
+    /// equal or at random in proportion to the weights otherwise.
+    fn pick_target(&self) -> usize {
+        let (_, first_weight) = self.targets[0];
+        if self
+            .targets
+            .iter()
+            .all(|(_, weight)| *weight == first_weight)
+        {
+            return self.next_target.fetch_add(1, Ordering::Relaxed) % self.targets.len();
+        }
+        let total_weight: u32 = self.targets.iter().map(|(_, weight)| weight).sum();
+        let mut choice = rand::rng().random_range(0..total_weight);
+        for (index, (_, weight)) in self.targets.iter().enumerate() {
+            if choice < *weight {
+                return index;
+            }
+            choice -= weight;
+        }
+        self.targets.len() - 1
+    }
+
+    /// Builds the HTTP(S) URL for a path on the given target.
+    fn url(&self, base_url: &str, path: &str) -> String {
+        let scheme = if self.secure { "https" } else { "http" };
+        format!("{scheme}://{base_url}{path}")
+    }
+
+    /// Builds the WebSocket URL for a path on the given target.
+    fn websocket_url(&self, base_url: &str, path: &str) -> String {
+        let scheme = if self.secure { "wss" } else { "ws" };
+        format!("{scheme}://{base_url}{path}")
+    }
+
+    /// Request body for the data, throttled if there's an upload limit.
+    fn body(&self, data: &Bytes) -> reqwest::Body {
+        match self.max_upload_bps {
+            Some(bps) => reqwest::Body::wrap_stream(throttled_body(
+                data.clone(),
+                bps,
+                Arc::clone(&self.bandwidth),
+            )),
+            None => reqwest::Body::from(data.clone()),
+        }
+    }
+
+    /// Reads a response body, throttled if there's a download limit.
+    async fn read_body(&self, mut response: reqwest::Response) -> color_eyre::Result<Bytes> {
+        let too_large = || -> color_eyre::Report { InvalidResponse("response too large").into() };
+        if let Some(max) = self.max_response_size
+            && response.content_length().is_some_and(|length| length > max)
+        {
+            return Err(too_large());
+        }
+        match (self.max_download_bps, self.max_response_size) {
+            (Some(bps), max) => throttled_read(response, bps, max, &self.bandwidth).await,
+            (None, Some(max)) => {
+                let mut body = BytesMut::new();
+                while let Some(chunk) = response.chunk().await? {
+                    if (body.len() + chunk.len()) as u64 > max {
+                        return Err(too_large());
+                    }
+                    body.extend_from_slice(&chunk);
+                }
+                Ok(body.freeze())
+            }
+            (None, None) => Ok(response.bytes().await?),
+        }
+    }
+
+    /// Endpoint of the worker with the given index, from the mix if there is one.
+    fn worker_endpoint(&self, mut worker: usize) -> Endpoint {
+        for (endpoint, concurrency) in self.mix.iter() {
+            if worker < *concurrency {
+                return *endpoint;
+            }
+            worker -= concurrency;
+        }
+        self.endpoint
+    }
+
+    /// Payload bytes sent and received by a successful request of the given size to the endpoint.
+    fn payload_bytes(endpoint: Endpoint, size: usize) -> u64 {
+        let size = size as u64;
+        match endpoint {
+            Endpoint::Get
+            | Endpoint::Post
+            | Endpoint::Bytes
+            | Endpoint::Gzip
+            | Endpoint::Deflate
+            | Endpoint::Trailers => size,
+            // The message is echoed back.
+            Endpoint::Websocket => size * 2,
+            Endpoint::Sse | Endpoint::Ping => 0,
+        }
+    }
+
+    /// Counts the payload of a successful request, stopping the benchmark once the byte cap is
+    /// reached.
+    fn record_transfer(&self, endpoint: Endpoint, size: usize) {
+        let bytes = Self::payload_bytes(endpoint, size);
+        let transferred = self.transferred.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(max_bytes) = self.max_bytes
+            && transferred >= max_bytes
+            && !self.stop.swap(true, Ordering::Relaxed)
+        {
+            info!(
+                transferred,
+                max_bytes, "Byte cap reached, stopping benchmark."
+            );
+        }
+    }
+
+    /// The benchmark's own request, with its path template if one is set.
+    fn spec(&self) -> RequestSpec<'_> {
+        RequestSpec {
+            path: self.path.as_deref(),
+            data: self.data.clone(),
+            size: self.size,
+        }
+    }
+
+    /// Path for a request, from the path template if one is set, or else from the endpoint's own
+    /// template.
+    fn request_path(&self, default: &str) -> String {
+        self.spec().path(default)
+    }
+}
+
+/// Runs the benchmark, and returns its results. Failed SLA thresholds and regressions from the
+/// baseline are returned along with them, for `BenchmarkStats::check` to turn into an error.
+pub async fn entrypoint(config: EntrypointConfig) -> color_eyre::Result<BenchmarkStats> {
+    run(config, false)
+        .await?
+        .ok_or_else(|| eyre!("The benchmark didn't run."))
+}
+
+/// Only round-trips a small GET, POST and WebSocket exchange with each target, without running
+/// the benchmark.
+pub async fn selftest(config: EntrypointConfig) -> color_eyre::Result<()> {
+    run(config, true).await.map(|_| ())
+}
+
+/// Runs the benchmark once for each of the sizes, one after the other, and logs how the results
+/// scale with the size.
+pub async fn sweep_sizes(
+    config: EntrypointConfig,
+    sizes: Vec<usize>,
+) -> color_eyre::Result<Vec<BenchmarkStats>> {
+    if sizes.is_empty() {
+        return Err(eyre!("A size sweep needs at least one size."));
+    }
+    if config.body_file.is_some() {
+        return Err(eyre!("Size sweeps don't support body files."));
+    }
+    if config.baseline.is_some() {
+        return Err(eyre!("Size sweeps don't support baselines."));
+    }
+    // Each run would overwrite the previous one's files.
+    let files = [
+        &config.json,
+        &config.prom_file,
+        &config.timeseries,
+        &config.records_file,
+    ];
+    if files.iter().any(|file| file.is_some()) {
+        return Err(eyre!("Size sweeps don't support writing results to files."));
+    }
+    let mut results = Vec::with_capacity(sizes.len());
+    for &size in sizes.iter() {
+        info!(size, "Sweeping size.");
+        results.push(
+            entrypoint(EntrypointConfig {
+                size,
+                ..config.clone()
+            })
+            .await?,
+        );
+    }
+    for (size, stats) in sizes.iter().zip(results.iter()) {
+        let throughput = stats.bytes as f64 / stats.elapsed.as_secs_f64();
+        info!(
+            target: RESULTS_TARGET,
+            size,
+            rps = format!("{:.2}", stats.rps),
+            p99 = format_latency(stats.p99),
+            bytes_per_second = format!("{throughput:.0}"),
+            error_rate = format!("{:.4}", stats.error_rate),
+            "Size sweep result."
+        );
+    }
+    Ok(results)
+}
+
+/// Sets up the benchmark and runs it, or stops after the self-test if `selftest_only` is set.
+async fn run(
+    config: EntrypointConfig,
+    selftest_only: bool,
+) -> color_eyre::Result<Option<BenchmarkStats>> {
+    // Logged in full, so that archived logs describe the run they come from.
+    let version = env!("CARGO_PKG_VERSION");
+    info!(version, ?config, "Effective configuration.");
+    if config.config_json {
+        info!(
+            version,
+            config = %serde_json::to_string(&config)?,
+            "Effective configuration as JSON."
+        );
+    }
+    let EntrypointConfig {
+        targets,
+        direct,
+        uds,
         endpoint,
+        mix,
+        path,
         size,
         concurrency,
+        requests,
+        duration,
+        rate,
+        connections_per_second,
+        max_inflight,
+        think_time,
+        keepalive,
+        connections,
+        http2,
+        http3,
+        socket,
+        cookies,
+        max_upload_bps,
+        max_download_bps,
+        max_response_size,
+        body_file,
+        payload_pattern,
+        sse_events,
+        websocket_persistent,
+        websocket_retries,
+        post_info,
+        multipart,
+        timing_breakdown,
+        handshake_only,
+        expect_continue,
+        pipeline,
         custom_ca_cert,
+        client_cert,
+        client_key,
         host_ip: host,
-    }: EntrypointConfig,
-) -> color_eyre::Result<()> {
-    let base_url: &'static str = base_url
-        .leak()
-        .trim_start_matches("https://")
-        .trim_start_matches("http://")
-        .trim_end_matches("/");
-    let mut jhs = Vec::with_capacity(concurrency);
-    let initial_data = match endpoint {
-        Endpoint::Get => Bytes::new(),
-        Endpoint::Post | Endpoint::Websocket => {
-            let mut buf = vec![0u8; size];
-            rand::rng().fill_bytes(&mut buf);
-            Bytes::from(buf)
+        resolve,
+        proxy,
+        headers,
+        user_agent,
+        auth,
+        autoscale,
+        hol_blocking,
+        replay,
+        sla,
+        abort,
+        json,
+        prom_file,
+        timeseries,
+        records_file,
+        snapshot_interval,
+        baseline,
+        max_bytes,
+        fail_fast,
+        error_for_status,
+        recovery_window,
+        selftest,
+        start_at,
+        seed,
+        expect_content_type,
+        verify,
+        config_json: _,
+    } = config;
+    // Requests over a Unix socket still need a host, which the service ignores.
+    let direct = match (direct, &uds) {
+        (Some(_), Some(_)) => {
+            return Err(eyre!(
+                "Direct mode and Unix sockets can't be used together."
+            ));
+        }
+        (None, Some(_)) if !cfg!(unix) => {
+            return Err(eyre!("Unix sockets are only supported on Unix platforms."));
+        }
+        (None, Some(_)) => Some("localhost".to_string()),
+        (direct, None) => direct,
+    };
+    if uds.is_some() && proxy.is_some() {
+        return Err(eyre!("Unix sockets don't support proxies."));
+    }
+    let secure = direct.is_none();
+    let targets = match direct {
+        Some(_) if !targets.is_empty() => {
+            return Err(eyre!("Direct mode doesn't accept target URLs."));
+        }
+        Some(addr) => vec![Target {
+            url: addr,
+            weight: 1,
+        }],
+        None if targets.is_empty() => {
+            return Err(eyre!("At least one target URL is required."));
         }
+        None => targets,
     };
-    let config = custom_ca_cert
+    if mix.iter().any(|(_, concurrency)| *concurrency == 0) {
+        return Err(eyre!(
+            "Each endpoint of the mix needs a positive concurrency."
+        ));
+    }
+    if mix.is_empty() && concurrency == 0 {
+        return Err(eyre!("The concurrency must be positive."));
+    }
+    if let Some(hol_blocking) = &hol_blocking {
+        // Only streams on a shared connection can block behind each other.
+        if !http2 {
+            return Err(eyre!("Head-of-line blocking mode requires HTTP/2."));
+        }
+        if endpoint != Endpoint::Get || !mix.is_empty() {
+            return Err(eyre!(
+                "Head-of-line blocking mode only supports the GET endpoint."
+            ));
+        }
+        if autoscale.is_some() || replay.is_some() {
+            return Err(eyre!(
+                "Head-of-line blocking mode doesn't support autoscaling or replays."
+            ));
+        }
+        if hol_blocking.large_concurrency == 0 {
+            return Err(eyre!("At least one large transfer is required."));
+        }
+    }
+    if !mix.is_empty() && (autoscale.is_some() || replay.is_some()) {
+        return Err(eyre!(
+            "Mixed workloads don't support autoscaling or replays."
+        ));
+    }
+    // A mixed workload runs one pool of workers per endpoint, all at once.
+    let concurrency = if mix.is_empty() {
+        concurrency
+    } else {
+        mix.iter().map(|(_, concurrency)| concurrency).sum()
+    };
+    let endpoints: Vec<Endpoint> = if mix.is_empty() {
+        vec![endpoint]
+    } else {
+        mix.iter().map(|(endpoint, _)| *endpoint).collect()
+    };
+    // An empty upload wouldn't measure anything, and would look like a successful run.
+    if size == 0
+        && body_file.is_none()
+        && endpoints
+            .iter()
+            .any(|endpoint| matches!(endpoint, Endpoint::Post | Endpoint::Websocket))
+    {
+        return Err(eyre!(
+            "POST and WebSocket requests need a positive size, or a body file."
+        ));
+    }
+    if start_at.is_some_and(|start_at| start_at <= SystemTime::now()) {
+        return Err(eyre!("The start time is already in the past."));
+    }
+    if path.as_ref().is_some_and(|path| !path.starts_with('/')) {
+        return Err(eyre!("The path template must start with a slash."));
+    }
+    // A connection storm paces fresh, timed connections instead of requests on pooled ones.
+    let (rate, timing_breakdown) = match connections_per_second {
+        Some(connections_per_second) if connections_per_second <= 0.0 => {
+            return Err(eyre!("The connection rate must be positive."));
+        }
+        Some(connections_per_second) => (Some(connections_per_second), true),
+        None => (rate, timing_breakdown),
+    };
+    // Replayed entries keep their own timing and are sent through the regular handler.
+    if replay.is_some() && (autoscale.is_some() || rate.is_some() || timing_breakdown) {
+        return Err(eyre!(
+            "Replays don't support autoscaling, a target rate or the timing breakdown."
+        ));
+    }
+    if handshake_only && !secure {
+        return Err(eyre!("Handshake-only mode doesn't support direct mode."));
+    }
+    // reqwest sends the body right away, so waiting for 100 Continue needs the low-level
+    // connector.
+    if expect_continue && (endpoint != Endpoint::Post || !mix.is_empty() || post_info) {
+        return Err(eyre!(
+            "Expect: 100-continue only supports the POST endpoint."
+        ));
+    }
+    if expect_continue && !secure {
+        return Err(eyre!("Expect: 100-continue doesn't support direct mode."));
+    }
+    // Neither reqwest nor hyper pipelines requests, so it also needs the low-level connector.
+    if pipeline.is_some() && (endpoint != Endpoint::Get || !mix.is_empty()) {
+        return Err(eyre!("Pipelining only supports the GET endpoint."));
+    }
+    if pipeline.is_some() && !secure {
+        return Err(eyre!("Pipelining doesn't support direct mode."));
+    }
+    if pipeline == Some(0) {
+        return Err(eyre!("The pipelining depth must be positive."));
+    }
+    // Handshakes go through the same low-level connector as the timing breakdown.
+    let timing_breakdown =
+        timing_breakdown || handshake_only || expect_continue || pipeline.is_some();
+    if timing_breakdown && multipart {
+        return Err(eyre!("Timing breakdown doesn't support multipart uploads."));
+    }
+    if timing_breakdown && !secure {
+        return Err(eyre!("Timing breakdown doesn't support direct mode."));
+    }
+    if timing_breakdown && !mix.is_empty() {
+        return Err(eyre!("Timing breakdown doesn't support mixed workloads."));
+    }
+    if timing_breakdown && !handshake_only && !matches!(endpoint, Endpoint::Get | Endpoint::Post) {
+        return Err(eyre!(
+            "Timing breakdown only supports the GET and POST endpoints."
+        ));
+    }
+    if websocket_persistent && !endpoints.contains(&Endpoint::Websocket) {
+        return Err(eyre!(
+            "Persistent connections require the WebSocket endpoint."
+        ));
+    }
+    if http3 {
+        if !cfg!(feature = "http3") {
+            return Err(eyre!(
+                "HTTP/3 requires building measure with the `http3` feature."
+            ));
+        }
+        // QUIC is always encrypted, and runs over UDP instead of the tunnel's TCP streams.
+        if !secure {
+            return Err(eyre!("HTTP/3 doesn't support direct mode."));
+        }
+        if timing_breakdown {
+            return Err(eyre!("HTTP/3 doesn't support the timing breakdown."));
+        }
+        if proxy.is_some() {
+            return Err(eyre!("HTTP/3 doesn't support proxies."));
+        }
+        if endpoints.contains(&Endpoint::Websocket) {
+            return Err(eyre!("HTTP/3 doesn't support WebSocket upgrades."));
+        }
+    }
+    let targets: Vec<(&'static str, u32)> = targets
+        .into_iter()
+        .map(|Target { url, weight }| {
+            let base_url: &'static str = url
+                .leak()
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .trim_end_matches("/");
+            (base_url, weight)
+        })
+        .collect();
+    let root_store = custom_ca_cert
         .map(|path| {
             CertificateDer::pem_file_iter(path)
                 .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
                 .map(|certs| {
                     let mut root_store = RootCertStore::empty();
                     root_store.add_parsable_certificates(certs);
-                    ClientConfig::builder_with_provider(Arc::new(
-                        rustls::crypto::aws_lc_rs::default_provider(),
-                    ))
-                    .with_safe_default_protocol_versions()
-                    .unwrap()
-                    .with_root_certificates(root_store)
-                    .with_no_client_auth()
+                    root_store
                 })
         })
         .transpose()?;
-    let client = if let Some(config) = config {
-        let client = reqwest::Client::builder().tls_backend_preconfigured(config);
-        if let Some(host) = host {
-            client
-                .resolve(
+    let client_identity = match (client_cert, client_key) {
+        (Some(cert), Some(key)) => Some(load_client_identity(&cert, &key)?),
+        (Some(_), None) | (None, Some(_)) => {
+            return Err(eyre!(
+                "A client certificate and its key must be given together."
+            ));
+        }
+        (None, None) => None,
+    };
+    // The client certificate goes in our own TLS configuration, so that it's presented both by
+    // reqwest (including WebSocket upgrades) and by the timing breakdown's connector.
+    let config = if root_store.is_some() || client_identity.is_some() {
+        let builder = ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store.unwrap_or_else(native_root_store));
+        Some(match client_identity {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs, key)
+                .wrap_err_with(|| "Client certificate doesn't match its key.")?,
+            None => builder.with_no_client_auth(),
+        })
+    } else {
+        None
+    };
+    let mut header_map = HeaderMap::new();
+    for Header { name, value } in headers {
+        header_map.append(
+            HeaderName::try_from(&name).wrap_err_with(|| format!("Invalid header name {name}."))?,
+            HeaderValue::try_from(&value)
+                .wrap_err_with(|| format!("Invalid value for header {name}."))?,
+        );
+    }
+    if !header_map.contains_key(USER_AGENT) {
+        header_map.insert(
+            USER_AGENT,
+            HeaderValue::try_from(&user_agent).wrap_err_with(|| "Invalid user agent.")?,
+        );
+    }
+    if let Some(auth) = auth {
+        header_map.insert(AUTHORIZATION, auth.header_value()?);
+    }
+    let probe = timing_breakdown.then(|| {
+        let tls_config = config.clone().unwrap_or_else(native_tls_config);
+        Probe::new(
+            tls_config,
+            host,
+            resolve.clone(),
+            &header_map,
+            socket,
+            expect_continue,
+        )
+    });
+    if connections == 0 {
+        return Err(eyre!("At least one connection is required."));
+    }
+    let build_client = || -> color_eyre::Result<reqwest::Client> {
+        let client = if let Some(config) = config.clone() {
+            reqwest::Client::builder().tls_backend_preconfigured(config)
+        } else {
+            reqwest::Client::builder().tls_backend_rustls()
+        };
+        let client = if let Some(host) = host {
+            targets.iter().fold(client, |client, (base_url, _)| {
+                client.resolve(
                     base_url
                         .split_once(':')
                         .map(|(first, _)| first)
                         .unwrap_or(base_url),
                     host,
                 )
-                .build()?
+            })
+        } else {
+            client
+        };
+        let client = client
+            .default_headers(header_map.clone())
+            .cookie_store(cookies)
+            .tcp_nodelay(socket.nodelay)
+            .local_address(socket.local_address);
+        let client = resolve.iter().fold(client, |client, entry| {
+            client.resolve(&entry.host, entry.addr)
+        });
+        let client = if let Some(proxy) = &proxy {
+            client.proxy(build_proxy(proxy)?)
+        } else {
+            client
+        };
+        #[cfg(unix)]
+        let client = if let Some(uds) = &uds {
+            client.unix_socket(uds.clone())
+        } else {
+            client
+        };
+        let client = if http2 {
+            client.http2_prior_knowledge()
+        } else {
+            client
+        };
+        #[cfg(feature = "http3")]
+        let client = if http3 {
+            client.http3_prior_knowledge()
+        } else {
+            client
+        };
+        // Without idle connections in the pool, every request must open a fresh connection
+        // through the tunnel.
+        let client = if keepalive {
+            client
+        } else {
+            client.pool_max_idle_per_host(0)
+        };
+        Ok(client.build()?)
+    };
+    let clients = (0..connections)
+        .map(|_| build_client())
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+    // Sizes over the service's limit would only yield errors, so clamp them to it.
+    let limited = endpoints.iter().any(|endpoint| {
+        !matches!(
+            endpoint,
+            Endpoint::Websocket | Endpoint::Sse | Endpoint::Ping
+        )
+    });
+    let size = match body_file {
+        None if limited => {
+            let scheme = if secure { "https" } else { "http" };
+            let url = format!("{scheme}://{}/limits", targets[0].0);
+            match fetch_limits(&clients[0], &url).await {
+                Ok(limits) => {
+                    let limit = endpoints
+                        .iter()
+                        .filter_map(|endpoint| limits.limit(*endpoint))
+                        .min();
+                    match limit {
+                        Some(limit) if size > limit => {
+                            warn!(
+                                size,
+                                limit, "Size exceeds the service's maximum, clamping it."
+                            );
+                            limit
+                        }
+                        _ => size,
+                    }
+                }
+                Err(error) => {
+                    debug!(?error, "Unable to fetch the service's limits.");
+                    size
+                }
+            }
+        }
+        _ => size,
+    };
+    let (initial_data, size) = if let Some(path) = body_file {
+        let data = Bytes::from(
+            tokio::fs::read(&path)
+                .await
+                .wrap_err_with(|| format!("Unable to read body file {}.", path.display()))?,
+        );
+        let size = data.len();
+        (data, size)
+    } else {
+        let uploads = endpoints.iter().any(|endpoint| match endpoint {
+            Endpoint::Get
+            | Endpoint::Sse
+            | Endpoint::Bytes
+            | Endpoint::Gzip
+            | Endpoint::Deflate
+            | Endpoint::Trailers
+            | Endpoint::Ping => false,
+            Endpoint::Post | Endpoint::Websocket => true,
+        });
+        let data = if uploads {
+            let mut buf = vec![0u8; size];
+            payload_pattern.fill(&mut rand::rng(), &mut buf);
+            Bytes::from(buf)
         } else {
-            client.build()?
+            Bytes::new()
+        };
+        (data, size)
+    };
+    let mode = if handshake_only {
+        "handshake only"
+    } else if connections_per_second.is_some() {
+        "connection storm"
+    } else if keepalive {
+        "keep-alive"
+    } else {
+        "fresh connection"
+    };
+    let expected = if verify {
+        let seed = seed.ok_or_else(|| eyre!("Verification requires a seed."))?;
+        Some(seeded_data(
+            seed,
+            payload_pattern,
+            size + usize::from(u16::MAX),
+        ))
+    } else {
+        None
+    };
+    let replay = match replay {
+        Some(ReplayConfig { path, speed }) => {
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err(eyre!("The replay speed must be positive."));
+            }
+            let contents = tokio::fs::read_to_string(&path)
+                .await
+                .wrap_err_with(|| format!("Unable to read replay log {}.", path.display()))?;
+            let entries = parse_replay(&contents)
+                .wrap_err_with(|| format!("Invalid replay log {}.", path.display()))?;
+            info!(requests = entries.len(), speed, "Replaying request log.");
+            Some(Replay::new(entries, speed))
         }
+        None => None,
+    };
+    let sessions = targets.iter().map(|_| AtomicU64::new(0)).collect();
+    let benchmark = Arc::new(Benchmark {
+        targets,
+        secure,
+        next_target: AtomicUsize::new(0),
+        clients,
+        endpoint,
+        mix,
+        path,
+        data: initial_data,
+        size,
+        requests,
+        duration,
+        rate,
+        inflight: max_inflight.map(Semaphore::new),
+        think_time,
+        sse_events,
+        websocket_persistent,
+        websocket_retries,
+        websocket_reconnects: AtomicUsize::new(0),
+        post_info,
+        multipart,
+        probe,
+        handshake_only,
+        pipeline,
+        expected,
+        expect_content_type,
+        abort: abort.map(|AbortConfig { max_p99, window }| (max_p99, SlidingWindow::new(window))),
+        timeseries: timeseries.is_some().then(Timeseries::new),
+        records: records_file.is_some().then(Records::new),
+        max_upload_bps,
+        max_download_bps,
+        max_response_size,
+        bandwidth: Arc::default(),
+        snapshot: snapshot_interval.map(|interval| (interval, Snapshot::default())),
+        max_bytes,
+        transferred: AtomicU64::new(0),
+        fail_fast,
+        error_for_status,
+        recovery_window,
+        sessions,
+        connection_ids: Mutex::new(HashSet::new()),
+        reconnected_at: Mutex::new(None),
+        replay,
+        stop: AtomicBool::new(false),
+    });
+    info!(
+        targets = ?benchmark.targets,
+        direct = !benchmark.secure,
+        %endpoint,
+        mix = ?benchmark.mix,
+        path = ?benchmark.path,
+        %size,
+        %concurrency,
+        %requests,
+        ?duration,
+        ?rate,
+        ?connections_per_second,
+        ?max_inflight,
+        ?think_time,
+        %mode,
+        ?socket,
+        %cookies,
+        %verify,
+        %connections,
+        %http2,
+        %http3,
+        client = ?benchmark.clients[0],
+        "Starting benchmark..."
+    );
+    if selftest || selftest_only {
+        for (base_url, _) in benchmark.targets.iter() {
+            self_test(&benchmark, base_url)
+                .await
+                .wrap_err_with(|| format!("Self-test failed for {base_url}."))?;
+            info!(%base_url, "Self-test passed.");
+        }
+    }
+    if selftest_only {
+        return Ok(None);
+    }
+    if let Some(start_at) = start_at {
+        match start_at.duration_since(SystemTime::now()) {
+            Ok(wait) => {
+                info!(
+                    start_at = %humantime::format_rfc3339(start_at),
+                    wait = humantime::format_duration(wait).to_string(),
+                    "Waiting for the start time."
+                );
+                tokio::time::sleep(wait).await;
+            }
+            Err(_) => warn!("The start time passed during setup, starting now."),
+        }
+    }
+    let stats = if let Some(autoscale_config) = autoscale {
+        autoscale_stages(&benchmark, concurrency, autoscale_config).await?
+    } else if let Some(hol_blocking) = hol_blocking {
+        hol_blocking_stages(&benchmark, concurrency, hol_blocking).await?
     } else {
-        let client = reqwest::Client::builder().tls_backend_rustls();
-        if let Some(host) = host {
-            client
-                .resolve(
-                    base_url
-                        .split_once(':')
-                        .map(|(first, _)| first)
-                        .unwrap_or(base_url),
-                    host,
-                )
-                .build()?
+        run_stage(&benchmark, concurrency).await?
+    };
+    // Logged under its own target, so that it can be kept when everything else is quiet.
+    info!(
+        target: RESULTS_TARGET,
+        %mode,
+        concurrency = stats.concurrency,
+        total_requests = stats.requests(),
+        failures = stats.failures(),
+        connection_errors = stats.connection_errors,
+        dropped_connections = stats.dropped_connections,
+        synthesized = stats.synthesized,
+        statuses = ?stats.statuses,
+        invalid = ?stats.invalid,
+        close_codes = ?stats.close_codes,
+        rps = format!("{:.2}", stats.rps()),
+        p50 = format_latency(stats.percentile(50.0)),
+        p99 = format_latency(stats.percentile(99.0)),
+        elapsed = humantime::format_duration(stats.elapsed).to_string(),
+        "Benchmark finished."
+    );
+    if endpoints.contains(&Endpoint::Ping) {
+        // Without payloads, the tail is what tells tunnels apart.
+        info!(
+            p90 = format_latency(stats.percentile(90.0)),
+            p99 = format_latency(stats.percentile(99.0)),
+            p999 = format_latency(stats.percentile(99.9)),
+            p9999 = format_latency(stats.percentile(99.99)),
+            max = format_latency(stats.percentile(100.0)),
+            "Tail latency."
+        );
+    }
+    let connection_ids = benchmark.connection_ids.lock().unwrap().len();
+    if connection_ids > 0 {
+        info!(
+            connections = connection_ids,
+            "Distinct service connections used."
+        );
+    }
+    if stats.recovery_requests > 0 {
+        info!(
+            recovery_requests = stats.recovery_requests,
+            steady_p50 = format_latency(stats.steady_percentile(50.0)),
+            steady_p99 = format_latency(stats.steady_percentile(99.0)),
+            recovery_p50 = format_latency(stats.recovery_percentile(50.0)),
+            recovery_p99 = format_latency(stats.recovery_percentile(99.0)),
+            "Reconnect recovery."
+        );
+    }
+    if expect_continue {
+        info!(
+            continued = stats.continued,
+            requests = stats.requests(),
+            "100 Continue responses received."
+        );
+    }
+    if endpoints.contains(&Endpoint::Sse) {
+        info!(
+            events = stats.sse.events,
+            dropped = stats.sse.dropped,
+            mean_gap = format_latency(stats.sse.mean_gap()),
+            max_gap = format_latency(stats.sse.max_gap),
+            "SSE events received."
+        );
+    }
+    if let Some(depth) = pipeline {
+        info!(
+            depth,
+            out_of_order = stats.out_of_order,
+            "Pipelined responses checked for ordering."
+        );
+    }
+    for phase in stats.phases() {
+        info!(
+            %phase,
+            p50 = format_latency(stats.phase_percentile(phase, 50.0)),
+            p99 = format_latency(stats.phase_percentile(phase, 99.0)),
+            "Connection phase timings."
+        );
+    }
+    for (target, target_stats) in stats.targets.iter() {
+        info!(
+            %target,
+            total_requests = target_stats.requests(),
+            failures = target_stats.failures(),
+            statuses = ?target_stats.statuses,
+            rps = format!("{:.2}", target_stats.rps()),
+            p50 = format_latency(target_stats.percentile(50.0)),
+            p99 = format_latency(target_stats.percentile(99.0)),
+            "Target finished."
+        );
+    }
+    let websocket_reconnects = benchmark.websocket_reconnects.load(Ordering::Relaxed);
+    if websocket_reconnects > 0 {
+        info!(websocket_reconnects, "Retried WebSocket upgrades.");
+    }
+    if let Some(consumed) = stats.retry_budget_consumed(websocket_retries) {
+        info!(
+            requests = stats.attempts.values().sum::<usize>(),
+            attempts = stats
+                .attempts
+                .iter()
+                .map(|(attempts, count)| attempts * count)
+                .sum::<usize>(),
+            attempts_per_request = ?stats.attempts,
+            consumed = format!("{:.2}%", consumed * 100.0),
+            "Retry budget consumed."
+        );
+    }
+    if let Some(upload_bps) = benchmark.bandwidth.upload_bps() {
+        info!(
+            max_upload_bps = ?benchmark.max_upload_bps,
+            upload_bps = format!("{upload_bps:.0}"),
+            "Achieved upload bandwidth per connection."
+        );
+    }
+    if let Some(download_bps) = benchmark.bandwidth.download_bps() {
+        info!(
+            max_download_bps = ?benchmark.max_download_bps,
+            download_bps = format!("{download_bps:.0}"),
+            "Achieved download bandwidth per connection."
+        );
+    }
+    for (endpoint, endpoint_stats) in stats.endpoints.iter() {
+        info!(
+            %endpoint,
+            concurrency = endpoint_stats.concurrency,
+            total_requests = endpoint_stats.requests(),
+            failures = endpoint_stats.failures(),
+            statuses = ?endpoint_stats.statuses,
+            rps = format!("{:.2}", endpoint_stats.rps()),
+            p50 = format_latency(endpoint_stats.percentile(50.0)),
+            p99 = format_latency(endpoint_stats.percentile(99.0)),
+            "Endpoint finished."
+        );
+    }
+    for (connection, connection_stats) in stats.connections.iter().enumerate() {
+        info!(
+            connection,
+            total_requests = connection_stats.requests(),
+            failures = connection_stats.failures(),
+            rps = format!("{:.2}", connection_stats.rps()),
+            p50 = format_latency(connection_stats.percentile(50.0)),
+            p99 = format_latency(connection_stats.percentile(99.0)),
+            "Connection finished."
+        );
+    }
+    if let Some((path, series)) = timeseries.zip(benchmark.timeseries.as_ref()) {
+        tokio::fs::write(&path, series.to_csv())
+            .await
+            .wrap_err_with(|| format!("Unable to write time series to {}.", path.display()))?;
+    }
+    if let Some((path, records)) = records_file.zip(benchmark.records.as_ref()) {
+        let endpoints = if benchmark.mix.is_empty() {
+            vec![benchmark.endpoint]
         } else {
-            client.build()?
+            benchmark
+                .mix
+                .iter()
+                .map(|(endpoint, _)| *endpoint)
+                .collect()
+        };
+        tokio::fs::write(
+            &path,
+            records.to_msgpack(
+                &benchmark.targets,
+                endpoints,
+                &stats,
+                size,
+                benchmark.transferred.load(Ordering::Relaxed),
+                benchmark.error_for_status,
+            )?,
+        )
+        .await
+        .wrap_err_with(|| format!("Unable to write records to {}.", path.display()))?;
+    }
+    let report = Report::new(
+        endpoint,
+        &stats,
+        websocket_retries,
+        benchmark.transferred.load(Ordering::Relaxed),
+    );
+    if let Some(path) = json {
+        tokio::fs::write(&path, serde_json::to_vec_pretty(&report)?)
+            .await
+            .wrap_err_with(|| format!("Unable to write results to {}.", path.display()))?;
+    }
+    if let Some(path) = prom_file {
+        // Write to a temporary file first, so that the collector never reads a partial file.
+        let temp = path.with_extension("prom.tmp");
+        tokio::fs::write(&temp, report.to_prometheus(size))
+            .await
+            .wrap_err_with(|| format!("Unable to write metrics to {}.", temp.display()))?;
+        tokio::fs::rename(&temp, &path)
+            .await
+            .wrap_err_with(|| format!("Unable to write metrics to {}.", path.display()))?;
+    }
+    let regressions = if let Some(BaselineConfig { path, tolerance }) = baseline {
+        let baseline: Report = serde_json::from_slice(
+            &tokio::fs::read(&path)
+                .await
+                .wrap_err_with(|| format!("Unable to read baseline {}.", path.display()))?,
+        )
+        .wrap_err_with(|| format!("Invalid baseline {}.", path.display()))?;
+        let regressions = report.compare(&baseline, tolerance);
+        if regressions.is_empty() {
+            info!("Baseline comparison passed.");
         }
+        regressions
+    } else {
+        Vec::new()
     };
-    info!(%base_url, %endpoint, %size, %concurrency, ?client, "Starting benchmark...");
+    for regression in regressions.iter() {
+        error!("Regressed from baseline: {regression}.");
+    }
+    let violations = sla.violations(&stats);
+    for violation in violations.iter() {
+        error!("SLA violated: {violation}.");
+    }
+    let mut stats = BenchmarkStats::new(stats, benchmark.transferred.load(Ordering::Relaxed));
+    stats.sla_violations = violations;
+    stats.regressions = regressions;
+    Ok(Some(stats))
+}
+
+/// Limits reported by the service's `/limits` endpoint.
+#[derive(Deserialize)]
+struct Limits {
+    max_data_size: usize,
+    /// Missing from older services, which limit POST bodies to `max_data_size`.
+    #[serde(default)]
+    max_post_size: Option<usize>,
+}
+
+impl Limits {
+    /// Largest size that the service accepts for the endpoint, if it limits it.
+    fn limit(&self, endpoint: Endpoint) -> Option<usize> {
+        match endpoint {
+            Endpoint::Get
+            | Endpoint::Bytes
+            | Endpoint::Gzip
+            | Endpoint::Deflate
+            | Endpoint::Trailers => Some(self.max_data_size),
+            Endpoint::Post => Some(self.max_post_size.unwrap_or(self.max_data_size)),
+            Endpoint::Websocket | Endpoint::Sse | Endpoint::Ping => None,
+        }
+    }
+}
+
+async fn fetch_limits(client: &reqwest::Client, url: &str) -> color_eyre::Result<Limits> {
+    let response = client.get(url).send().await?.error_for_status()?;
+    Ok(serde_json::from_slice(&response.bytes().await?)?)
+}
+
+/// Generates the same data as the service's GET buffer for the given seed and pattern. Since
+/// patterns are filled sequentially, any length yields a prefix of the service's buffer.
+fn seeded_data(seed: u64, pattern: PayloadPattern, len: usize) -> Bytes {
+    let mut data = vec![0u8; len];
+    pattern.fill(&mut StdRng::seed_from_u64(seed), &mut data);
+    Bytes::from(data)
+}
+
+/// The platform's native root certificates.
+fn native_root_store() -> RootCertStore {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    root_store
+}
+
+/// TLS configuration trusting the platform's native root certificates.
+fn native_tls_config() -> ClientConfig {
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(native_root_store())
+        .with_no_client_auth()
+}
+
+/// Loads a client certificate chain and its private key from PEM files, for mutual TLS.
+fn load_client_identity(
+    cert: &Path,
+    key: &Path,
+) -> color_eyre::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .wrap_err_with(|| format!("Unable to read client certificate {}.", cert.display()))?;
+    if certs.is_empty() {
+        return Err(eyre!("No certificates found in {}.", cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key)
+        .wrap_err_with(|| format!("Unable to read client key {}.", key.display()))?;
+    Ok((certs, key))
+}
+
+/// Parses a proxy URL for all requests, including WebSocket upgrades. Credentials may be passed in
+/// the URL's user info.
+fn build_proxy(url: &str) -> color_eyre::Result<reqwest::Proxy> {
+    let parsed = reqwest::Url::parse(url).wrap_err_with(|| "Malformed proxy URL.")?;
+    match parsed.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => (),
+        scheme => return Err(eyre!("Unsupported proxy scheme {scheme}.")),
+    }
+    reqwest::Proxy::all(parsed).wrap_err_with(|| "Invalid proxy URL.")
+}
+
+/// Runs a single benchmark stage with the given number of concurrent workers.
+async fn run_stage(
+    benchmark: &Arc<Benchmark>,
+    concurrency: usize,
+) -> color_eyre::Result<StageStats> {
+    let mut jhs = Vec::with_capacity(concurrency);
+    // Each worker sends at an even share of the target rate, staggered across the interval.
+    let interval = benchmark
+        .rate
+        .map(|rate| Duration::from_secs_f64(concurrency as f64 / rate));
     let started = Instant::now();
-    for _ in 0..concurrency {
-        let data = initial_data.clone();
-        let client = client.clone();
-        let jh = tokio::spawn(async move { handler(base_url, client, endpoint, data, size).await });
+    for i in 0..concurrency {
+        let benchmark = Arc::clone(benchmark);
+        let offset = interval
+            .map(|interval| interval.mul_f64(i as f64 / concurrency as f64))
+            .unwrap_or_default();
+        let scheduled = started + offset;
+        let deadline = benchmark.duration.map(|duration| started + duration);
+        let jh = tokio::spawn(async move {
+            debug!(
+                worker = i,
+                thread = ?std::thread::current().id(),
+                "Worker started."
+            );
+            let client = &benchmark.clients[i % benchmark.clients.len()];
+            let endpoint = benchmark.worker_endpoint(i);
+            worker(&benchmark, client, endpoint, interval, scheduled, deadline).await
+        });
         jhs.push(jh);
     }
-    let collected = try_join_all(jhs.into_iter()).await?;
+    let monitor = benchmark
+        .abort
+        .is_some()
+        .then(|| tokio::spawn(abort_monitor(Arc::clone(benchmark))));
+    let snapshots = benchmark
+        .snapshot
+        .is_some()
+        .then(|| tokio::spawn(snapshot_monitor(Arc::clone(benchmark))));
+    let collected = try_join_all(jhs.into_iter())
+        .await?
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(monitor) = monitor {
+        monitor.abort();
+    }
+    if let Some(snapshots) = snapshots {
+        snapshots.abort();
+    }
 
     let elapsed = started.elapsed();
-    collected.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let mut recorders: Vec<Recorder> = benchmark
+        .targets
+        .iter()
+        .map(|_| Recorder::default())
+        .collect();
+    let mut connection_recorders: Vec<Recorder> = benchmark
+        .clients
+        .iter()
+        .map(|_| Recorder::default())
+        .collect();
+    let mut endpoint_recorders: Vec<Recorder> =
+        benchmark.mix.iter().map(|_| Recorder::default()).collect();
+    for (i, worker_recorders) in collected.into_iter().enumerate() {
+        let connection_recorder = &mut connection_recorders[i % benchmark.clients.len()];
+        let endpoint = benchmark.worker_endpoint(i);
+        let mut endpoint_recorder = benchmark
+            .mix
+            .iter()
+            .position(|(mixed, _)| *mixed == endpoint)
+            .map(|index| &mut endpoint_recorders[index]);
+        for (recorder, worker_recorder) in recorders.iter_mut().zip(worker_recorders) {
+            connection_recorder.merge(worker_recorder.clone());
+            if let Some(endpoint_recorder) = &mut endpoint_recorder {
+                endpoint_recorder.merge(worker_recorder.clone());
+            }
+            recorder.merge(worker_recorder);
+        }
+    }
+    let mut total = Recorder::default();
+    for recorder in recorders.iter() {
+        total.merge(recorder.clone());
+    }
+    let mut stats = StageStats::new(concurrency, elapsed, total);
+    if recorders.len() > 1 {
+        stats.targets = benchmark
+            .targets
+            .iter()
+            .zip(recorders)
+            .map(|((base_url, _), recorder)| {
+                (
+                    base_url.to_string(),
+                    StageStats::new(concurrency, elapsed, recorder),
+                )
+            })
+            .collect();
+    }
+    stats.endpoints = benchmark
+        .mix
+        .iter()
+        .zip(endpoint_recorders)
+        .map(|((endpoint, concurrency), recorder)| {
+            (*endpoint, StageStats::new(*concurrency, elapsed, recorder))
+        })
+        .collect();
+    if connection_recorders.len() > 1 {
+        stats.connections = connection_recorders
+            .into_iter()
+            .map(|recorder| StageStats::new(concurrency, elapsed, recorder))
+            .collect();
+    }
+    Ok(stats)
+}
+
+/// Doubles the concurrency on each stage until the p99 latency degrades beyond the threshold, or
+/// until a stage has no successes or more errors than the baseline, returning the stats for the
+/// last stage that stayed within it.
+async fn autoscale_stages(
+    benchmark: &Arc<Benchmark>,
+    initial_concurrency: usize,
+    AutoscaleConfig {
+        max_concurrency,
+        threshold,
+    }: AutoscaleConfig,
+) -> color_eyre::Result<StageStats> {
+    let mut concurrency = initial_concurrency.max(1);
+    let baseline = run_stage(benchmark, concurrency).await?;
+    // Without successes, there's no latency to compare the other stages against.
+    if baseline.successes() == 0 {
+        return Err(eyre!("The autoscale baseline had no successful requests."));
+    }
+    let limit = baseline.percentile(99.0).mul_f64(threshold);
     info!(
-        elapsed = humantime::format_duration(elapsed).to_string(),
-        "Benchmark finished."
+        concurrency,
+        rps = format!("{:.2}", baseline.rps()),
+        p99 = format_latency(baseline.percentile(99.0)),
+        limit = format_latency(limit),
+        "Autoscale baseline finished."
     );
-    Ok(())
+    let baseline_error_rate = baseline.error_rate();
+    let mut knee = baseline;
+    while concurrency < max_concurrency && !benchmark.stop.load(Ordering::Relaxed) {
+        concurrency = (concurrency * 2).min(max_concurrency);
+        let stats = run_stage(benchmark, concurrency).await?;
+        let p99 = stats.percentile(99.0);
+        info!(
+            concurrency,
+            rps = format!("{:.2}", stats.rps()),
+            p99 = format_latency(p99),
+            "Autoscale stage finished."
+        );
+        if p99 > limit {
+            info!(
+                knee = knee.concurrency,
+                "p99 latency degraded beyond threshold, stopping autoscale."
+            );
+            break;
+        }
+        // A stage without successes has no latencies, so its p99 would look perfect.
+        if stats.successes() == 0 || stats.error_rate() > baseline_error_rate {
+            info!(
+                knee = knee.concurrency,
+                error_rate = format!("{:.2}%", stats.error_rate() * 100.0),
+                "Error rate rose above the baseline, stopping autoscale."
+            );
+            break;
+        }
+        knee = stats;
+    }
+    Ok(knee)
 }
 
-#[instrument(level = "debug")]
-async fn handler(
-    base_url: &str,
-    client: reqwest::Client,
+/// Runs the requests on their own, then again while large transfers are in flight on the same
+/// connections, and compares their p99 latencies. Returns the stats with the large transfers.
+async fn hol_blocking_stages(
+    benchmark: &Arc<Benchmark>,
+    concurrency: usize,
+    HolBlockingConfig {
+        large_size,
+        large_concurrency,
+    }: HolBlockingConfig,
+) -> color_eyre::Result<StageStats> {
+    let baseline = run_stage(benchmark, concurrency).await?;
+    info!(
+        p99 = format_latency(baseline.percentile(99.0)),
+        "Head-of-line blocking baseline finished."
+    );
+    let transfers = Arc::new(AtomicUsize::new(0));
+    let large = (0..large_concurrency)
+        .map(|i| {
+            let benchmark = Arc::clone(benchmark);
+            let transfers = Arc::clone(&transfers);
+            tokio::spawn(
+                async move { large_transfers(&benchmark, i, large_size, &transfers).await },
+            )
+        })
+        .collect::<Vec<_>>();
+    let loaded = run_stage(benchmark, concurrency).await;
+    for jh in large {
+        jh.abort();
+    }
+    let loaded = loaded?;
+    let (baseline_p99, loaded_p99) = (baseline.percentile(99.0), loaded.percentile(99.0));
+    info!(
+        target: RESULTS_TARGET,
+        large_size,
+        large_concurrency,
+        large_transfers = transfers.load(Ordering::Relaxed),
+        baseline_p99 = format_latency(baseline_p99),
+        loaded_p99 = format_latency(loaded_p99),
+        change = format!(
+            "{:+.1}%",
+            percent_change(baseline_p99.as_secs_f64(), loaded_p99.as_secs_f64())
+        ),
+        "Head-of-line blocking finished."
+    );
+    Ok(loaded)
+}
+
+/// Keeps downloading `size` bytes of GET data over the worker's client until aborted, counting
+/// the transfers that complete.
+async fn large_transfers(benchmark: &Benchmark, i: usize, size: usize, transfers: &AtomicUsize) {
+    let client = &benchmark.clients[i % benchmark.clients.len()];
+    loop {
+        let base_url = benchmark.targets[benchmark.pick_target()].0;
+        let url = benchmark.url(base_url, &format!("/get/{size}"));
+        let result = async {
+            client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        };
+        match result.await {
+            Ok(_) => {
+                transfers.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(error) => {
+                debug!(?error, "Large transfer failed.");
+                // Don't spin on a transfer that fails right away, such as one over the limit.
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// Periodically evaluates the windowed p99 latency, stopping the benchmark if it exceeds the limit.
+async fn abort_monitor(benchmark: Arc<Benchmark>) {
+    let Some((max_p99, window)) = &benchmark.abort else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        if let Some(p99) = window.percentile(99.0)
+            && p99 > *max_p99
+        {
+            warn!(
+                p99 = format_latency(p99),
+                max_p99 = format_latency(*max_p99),
+                "Windowed p99 latency over the limit, aborting benchmark."
+            );
+            benchmark.stop.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// Periodically logs a summary of the requests completed since the previous one, for watching
+/// long runs as they go.
+async fn snapshot_monitor(benchmark: Arc<Benchmark>) {
+    let Some((interval, snapshot)) = &benchmark.snapshot else {
+        return;
+    };
+    let started = Instant::now();
+    let mut ticker = tokio::time::interval(*interval);
+    // The first tick completes immediately.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let stats = snapshot.take();
+        let error_rate = if stats.requests == 0 {
+            0.0
+        } else {
+            (stats.requests - stats.successes) as f64 / stats.requests as f64 * 100.0
+        };
+        info!(
+            elapsed = humantime::format_duration(Duration::from_secs(started.elapsed().as_secs()))
+                .to_string(),
+            requests = stats.requests,
+            rps = format!("{:.2}", stats.successes as f64 / interval.as_secs_f64()),
+            p99 = format_latency(stats.p99),
+            error_rate = format!("{error_rate:.2}%"),
+            reconnects = stats.reconnects,
+            "Snapshot."
+        );
+    }
+}
+
+/// Runs the worker's requests back-to-back or, with an interval, at a fixed pace starting from
+/// the first scheduled instant. Stops after the deadline if set, or after the configured number of
+/// requests otherwise.
+async fn worker(
+    benchmark: &Benchmark,
+    client: &reqwest::Client,
     endpoint: Endpoint,
+    interval: Option<Duration>,
+    mut scheduled: Instant,
+    deadline: Option<Instant>,
+) -> color_eyre::Result<Vec<Recorder>> {
+    let mut recorders: Vec<Recorder> = benchmark
+        .targets
+        .iter()
+        .map(|_| Recorder::default())
+        .collect();
+    let mut completed = 0;
+    // Persistent WebSocket connections stay bound to a single target.
+    let fixed_target = benchmark
+        .websocket_persistent
+        .then(|| benchmark.pick_target());
+    let mut websocket = None;
+    let endpoint_index = benchmark
+        .mix
+        .iter()
+        .position(|(mixed, _)| *mixed == endpoint)
+        .unwrap_or(0);
+    let record_archived = |target, started, latency, status| {
+        if let Some(records) = &benchmark.records {
+            records.record(started, latency, status, target, endpoint_index);
+        }
+    };
+    let record_live = |latency| {
+        if let Some(timeseries) = &benchmark.timeseries {
+            timeseries.record(latency);
+        }
+        if let Some((_, snapshot)) = &benchmark.snapshot {
+            snapshot.record(latency);
+        }
+    };
+    // Replayed entries are sent when they're due, from their offsets since the worker started.
+    let paced = interval.is_some() || benchmark.replay.is_some();
+    let replay_started = scheduled;
+    loop {
+        let done = match deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            // A replay runs until its log is exhausted instead.
+            None => benchmark.replay.is_none() && completed >= benchmark.requests,
+        };
+        if done || benchmark.stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let (endpoint, spec) = match &benchmark.replay {
+            Some(replay) => {
+                let Some((endpoint, spec, due)) = replay.next(replay_started) else {
+                    break;
+                };
+                scheduled = due;
+                (endpoint, spec)
+            }
+            None => (endpoint, benchmark.spec()),
+        };
+        completed += 1;
+        let target = fixed_target.unwrap_or_else(|| benchmark.pick_target());
+        let recorder = &mut recorders[target];
+        if paced {
+            tokio::time::sleep_until(scheduled.into()).await;
+        }
+        let permit = match &benchmark.inflight {
+            Some(inflight) => Some(inflight.acquire().await?),
+            None => None,
+        };
+        let started = if paced { scheduled } else { Instant::now() };
+        let base_url = benchmark.targets[target].0;
+        // Sent as `X-Request-Id`, to correlate with the tunnel's and the service's logs.
+        let request_id = Uuid::new_v4().to_string();
+        if benchmark.websocket_persistent && endpoint == Endpoint::Websocket {
+            let was_connected = websocket.is_some();
+            match persistent_websocket_handler(
+                benchmark,
+                client,
+                base_url,
+                &mut websocket,
+                recorder,
+            )
+            .await
+            {
+                Ok(()) => {
+                    let latency = started.elapsed();
+                    record_live(Some(latency));
+                    record_archived(
+                        target,
+                        started,
+                        latency,
+                        Some(StatusCode::SWITCHING_PROTOCOLS),
+                    );
+                    recorder.record_message(latency);
+                    if benchmark.recovering(started) {
+                        recorder.record_recovery(Some(latency));
+                    }
+                    benchmark.record_transfer(endpoint, spec.size);
+                }
+                Err(error) if benchmark.fail_fast => {
+                    benchmark.stop.store(true, Ordering::Relaxed);
+                    return Err(error.wrap_err("Persistent WebSocket request failed."));
+                }
+                Err(error) if was_connected => {
+                    record_live(None);
+                    record_archived(target, started, started.elapsed(), None);
+                    if let Some((_, snapshot)) = &benchmark.snapshot {
+                        snapshot.record_reconnect();
+                    }
+                    debug!(?error, "Persistent WebSocket connection dropped.");
+                    if let Some(WebsocketClosed(code)) = error.downcast_ref::<WebsocketClosed>() {
+                        recorder.record_close(*code);
+                    }
+                    recorder.record_dropped_connection();
+                    benchmark.detect_reconnect();
+                    recorder.record_recovery(None);
+                }
+                Err(error) => {
+                    record_live(None);
+                    record_archived(target, started, started.elapsed(), None);
+                    debug!(?error, "Persistent WebSocket connection failed.");
+                    recorder.record_connection_error();
+                }
+            }
+        } else {
+            // The ID, status and latency of each response, of which there are several when
+            // pipelining.
+            let single = |status: StatusCode| vec![(request_id.clone(), status, started.elapsed())];
+            let result = match &benchmark.probe {
+                Some(probe) if benchmark.handshake_only => probe
+                    .handshake(base_url)
+                    .await
+                    .map(|phases| {
+                        recorder.record_phases(&phases);
+                        StatusCode::OK
+                    })
+                    .map(single),
+                Some(probe) if benchmark.pipeline.is_some() => {
+                    let depth = benchmark.pipeline.unwrap_or_default();
+                    // Each of the batch's requests counts towards the total, not just the batch.
+                    completed += depth.saturating_sub(1);
+                    let request_ids: Vec<String> =
+                        (0..depth).map(|i| format!("{request_id}-{i}")).collect();
+                    let path = spec.path("/get/{size}");
+                    probe
+                        .pipeline(base_url, &path, &request_ids)
+                        .await
+                        .map(|pipelined| {
+                            recorder.record_phases(&pipelined.phases);
+                            if !pipelined.in_order {
+                                recorder.record_out_of_order();
+                            }
+                            // Timed from the same start as any other request, setup included.
+                            request_ids
+                                .into_iter()
+                                .zip(pipelined.responses)
+                                .map(|(request_id, (status, received))| {
+                                    (request_id, status, received.duration_since(started))
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                }
+                Some(probe) => probe
+                    .request(
+                        base_url,
+                        benchmark.endpoint,
+                        &spec.data,
+                        &spec.path(match benchmark.endpoint {
+                            Endpoint::Post => "/post/{size}",
+                            _ => "/get/{size}",
+                        }),
+                        &request_id,
+                    )
+                    .await
+                    .map(|(status, phases)| {
+                        recorder.record_phases(&phases);
+                        status
+                    })
+                    .map(single),
+                None => handler(
+                    benchmark,
+                    client,
+                    endpoint,
+                    &spec,
+                    base_url,
+                    &request_id,
+                    recorder,
+                )
+                .await
+                .map(single),
+            };
+            match result {
+                Ok(responses) => {
+                    for (request_id, status, latency) in responses {
+                        if benchmark.fail_fast && !benchmark.succeeded(status) {
+                            benchmark.stop.store(true, Ordering::Relaxed);
+                            return Err(eyre!("Request {request_id} failed with status {status}."));
+                        }
+                        debug!(%request_id, %status, ?latency, "Request completed.");
+                        record_live(benchmark.succeeded(status).then_some(latency));
+                        record_archived(target, started, latency, Some(status));
+                        if let Some((_, window)) = &benchmark.abort
+                            && benchmark.succeeded(status)
+                        {
+                            window.record(latency);
+                        }
+                        benchmark.record_response(recorder, status, latency);
+                        if benchmark.recovering(started) {
+                            recorder
+                                .record_recovery(benchmark.succeeded(status).then_some(latency));
+                        }
+                        if benchmark.succeeded(status) {
+                            benchmark.record_transfer(endpoint, spec.size);
+                        }
+                    }
+                }
+                Err(error) if error.is::<SizeExceeded>() => {
+                    // Every other request would fail the same way.
+                    benchmark.stop.store(true, Ordering::Relaxed);
+                    return Err(error);
+                }
+                Err(error) if benchmark.fail_fast => {
+                    benchmark.stop.store(true, Ordering::Relaxed);
+                    return Err(error.wrap_err(format!("Request {request_id} failed.")));
+                }
+                Err(error) => {
+                    record_live(None);
+                    record_archived(target, started, started.elapsed(), None);
+                    if let Some(InvalidResponse(kind)) = error.downcast_ref::<InvalidResponse>() {
+                        debug!(%request_id, %kind, "Request received an invalid response.");
+                        recorder.record_invalid(*kind);
+                    } else {
+                        debug!(%request_id, ?error, "Request failed without a response.");
+                        if let Some(WebsocketClosed(code)) = error.downcast_ref::<WebsocketClosed>()
+                        {
+                            recorder.record_close(*code);
+                        }
+                        recorder.record_connection_error();
+                        // Requests failing without a response are the tunnel's reconnect pattern.
+                        benchmark.detect_reconnect();
+                        recorder.record_recovery(None);
+                    }
+                }
+            }
+        }
+        if let Some(interval) = interval {
+            scheduled += interval;
+            // Coordinated omission correction: the requests that should have been sent while this
+            // one stalled would have waited at least until now.
+            let now = Instant::now();
+            while scheduled < now {
+                recorder.record_synthesized(now - scheduled);
+                scheduled += interval;
+            }
+        }
+        drop(permit);
+        if let Some(ThinkTime { min, max }) = benchmark.think_time {
+            tokio::time::sleep(rand::rng().random_range(min..=max)).await;
+        }
+    }
+    Ok(recorders)
+}
+
+/// Sends a message over a persistent WebSocket connection and waits for its echo, opening the
+/// connection first if needed. The connection is discarded if anything fails.
+async fn persistent_websocket_handler(
+    benchmark: &Benchmark,
+    client: &reqwest::Client,
+    base_url: &str,
+    websocket: &mut Option<WebSocket>,
+    recorder: &mut Recorder,
+) -> color_eyre::Result<()> {
+    let connection = match websocket {
+        Some(connection) => connection,
+        None => {
+            let started = Instant::now();
+            let connection = websocket_connect(benchmark, client, base_url, None, recorder)
+                .await?
+                .map_err(|status| eyre!("WebSocket upgrade rejected with {status}."))?;
+            recorder.record_phase("handshake", started.elapsed());
+            websocket.insert(connection)
+        }
+    };
+    let started = Instant::now();
+    let result = websocket_echo(connection, benchmark.data.clone(), benchmark.size).await;
+    if result.is_ok() {
+        recorder.record_phase("message", started.elapsed());
+    } else {
+        *websocket = None;
+    }
+    result
+}
+
+/// Opens a WebSocket connection, retrying with backoff if the connection fails before the upgrade
+/// completes, such as when the tunnel resets it mid-handshake. Rejected upgrades aren't retried,
+/// and their status is returned instead.
+async fn websocket_connect(
+    benchmark: &Benchmark,
+    client: &reqwest::Client,
+    base_url: &str,
+    request_id: Option<&str>,
+    recorder: &mut Recorder,
+) -> color_eyre::Result<Result<WebSocket, StatusCode>> {
+    let connect = async || -> color_eyre::Result<Result<WebSocket, StatusCode>> {
+        let request = client.get(benchmark.websocket_url(base_url, &benchmark.request_path("/ws")));
+        let request = match request_id {
+            Some(request_id) => request.headers(request_headers(request_id)),
+            None => request,
+        };
+        let response = request.upgrade().send().await?;
+        benchmark.observe_session(base_url, response.headers());
+        let status = response.status();
+        if status != StatusCode::SWITCHING_PROTOCOLS {
+            return Ok(Err(status));
+        }
+        Ok(Ok(response.into_websocket().await?))
+    };
+    let mut attempts = 1;
+    let result = connect
+        .retry(
+            ExponentialBuilder::default()
+                .with_min_delay(Duration::from_millis(50))
+                .with_max_times(benchmark.websocket_retries),
+        )
+        .notify(|error, delay| {
+            attempts += 1;
+            benchmark
+                .websocket_reconnects
+                .fetch_add(1, Ordering::Relaxed);
+            debug!(?error, ?delay, "Retrying WebSocket upgrade.");
+        })
+        .await;
+    recorder.record_attempts(attempts);
+    result
+}
+
+/// Sends the data as a binary message and waits until it's echoed back.
+async fn websocket_echo(
+    websocket: &mut WebSocket,
     data: Bytes,
     size: usize,
 ) -> color_eyre::Result<()> {
+    websocket
+        .send(reqwest_websocket::Message::Binary(data))
+        .await?;
+    while let Some(message) = websocket.try_next().await? {
+        match message {
+            reqwest_websocket::Message::Binary(data) if data.len() == size => return Ok(()),
+            reqwest_websocket::Message::Close { code, reason } => {
+                let code = u16::from(code);
+                debug!(code, %reason, "WebSocket closed before the echo was received.");
+                return Err(WebsocketClosed(code).into());
+            }
+            _ => (),
+        }
+    }
+    Err(WebsocketClosed(1006).into())
+}
+
+/// Round-trips a small GET, POST and WebSocket exchange with a target, regardless of the
+/// benchmarked endpoint.
+async fn self_test(benchmark: &Benchmark, base_url: &str) -> color_eyre::Result<()> {
+    const SIZE: usize = 1024;
+    let client = &benchmark.clients[0];
+    let response = client
+        .get(benchmark.url(base_url, &format!("/get/{SIZE}")))
+        .send()
+        .await
+        .wrap_err_with(|| "GET request failed.")?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(eyre!("GET returned {status}."));
+    }
+    let received = response.bytes().await?.len();
+    if received != SIZE {
+        return Err(eyre!("GET returned {received} bytes instead of {SIZE}."));
+    }
+    let data = Bytes::from(vec![0u8; SIZE]);
+    let status = client
+        .post(benchmark.url(base_url, &format!("/post/{SIZE}")))
+        .body(data.clone())
+        .send()
+        .await
+        .wrap_err_with(|| "POST request failed.")?
+        .status();
+    if status != StatusCode::NO_CONTENT {
+        return Err(eyre!("POST returned {status}."));
+    }
+    let response = client
+        .get(benchmark.websocket_url(base_url, "/ws"))
+        .upgrade()
+        .send()
+        .await
+        .wrap_err_with(|| "WebSocket upgrade failed.")?;
+    let status = response.status();
+    if status != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(eyre!("WebSocket upgrade returned {status}."));
+    }
+    let mut websocket = response.into_websocket().await?;
+    websocket_echo(&mut websocket, data, SIZE)
+        .await
+        .wrap_err_with(|| "WebSocket echo failed.")?;
+    Ok(())
+}
+
+/// Error reported by the service when a requested size or POST body is over its configured limit.
+#[derive(Deserialize)]
+struct LimitExceeded {
+    limit: usize,
+}
+
+/// Logs the service's configured limit for a rejected POST body, and reports it as invalid.
+async fn over_limit(response: reqwest::Response, size: usize) -> color_eyre::Result<StatusCode> {
+    if let Ok(error) = serde_json::from_slice::<LimitExceeded>(&response.bytes().await?) {
+        debug!(
+            size,
+            limit = error.limit,
+            "POST body is over the service's limit."
+        );
+    }
+    Err(InvalidResponse("over limit").into())
+}
+
+/// Fatal error for a size that the service will never serve, so that the run stops instead of
+/// failing every request.
+#[derive(Debug)]
+struct SizeExceeded {
+    size: usize,
+    limit: usize,
+}
+
+impl Display for SizeExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "requested size {} exceeds server max {}",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for SizeExceeded {}
+
+/// Turns a GET rejection with the service's limit into a fatal error, or else returns the status
+/// as-is.
+async fn size_exceeded(response: reqwest::Response, size: usize) -> color_eyre::Result<StatusCode> {
+    let status = response.status();
+    match serde_json::from_slice::<LimitExceeded>(&response.bytes().await?) {
+        Ok(LimitExceeded { limit }) => Err(SizeExceeded { size, limit }.into()),
+        Err(_) => Ok(status),
+    }
+}
+
+/// Checks that the response has the expected `Content-Type`, if any is expected.
+fn check_content_type(
+    response: &reqwest::Response,
+    expected: Option<&str>,
+) -> Result<(), InvalidResponse> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let content_type = response.headers().get(CONTENT_TYPE);
+    if content_type.is_some_and(|content_type| content_type == expected) {
+        Ok(())
+    } else {
+        debug!(?content_type, expected, "Content type mismatch.");
+        Err(InvalidResponse("content type mismatch"))
+    }
+}
+
+/// Sizes reported by the service's `/post-info` endpoint.
+#[derive(Deserialize)]
+struct PostInfo {
+    received: usize,
+    expected: usize,
+}
+
+/// Headers identifying a request, with its trace context when exporting traces.
+fn request_headers(request_id: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(request_id) = HeaderValue::from_str(request_id) {
+        headers.insert("x-request-id", request_id);
+    }
+    #[cfg(feature = "otel")]
+    telemetry::inject_trace_context(&mut headers);
+    headers
+}
+
+/// Makes a single request, returning the final HTTP status of the exchange. Error statuses are
+/// returned as-is so that they can be tallied; only failures without a response are errors.
+#[instrument(level = "debug", skip_all, fields(%endpoint))]
+async fn handler(
+    benchmark: &Benchmark,
+    client: &reqwest::Client,
+    endpoint: Endpoint,
+    spec: &RequestSpec<'_>,
+    base_url: &str,
+    request_id: &str,
+    recorder: &mut Recorder,
+) -> color_eyre::Result<StatusCode> {
+    let RequestSpec { data, size, .. } = spec;
+    let Benchmark {
+        sse_events,
+        post_info,
+        multipart,
+        expected,
+        expect_content_type,
+        ..
+    } = benchmark;
     match endpoint {
         Endpoint::Get => {
-            client
-                .get(format!("https://{base_url}/get/{size}"))
+            let response = client
+                .get(benchmark.url(base_url, &spec.path("/get/{size}")))
+                .headers(request_headers(request_id))
                 .send()
-                .await?
-                .error_for_status()?
-                .bytes()
                 .await?;
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if status == StatusCode::BAD_REQUEST {
+                return size_exceeded(response, *size).await;
+            }
+            if is_success(status) {
+                check_content_type(&response, expect_content_type.as_deref())?;
+                let offset = response
+                    .headers()
+                    .get("x-data-offset")
+                    .and_then(|offset| offset.to_str().ok())
+                    .and_then(|offset| offset.parse::<usize>().ok());
+                let body = benchmark.read_body(response).await?;
+                if body.len() != *size {
+                    debug!(
+                        received = body.len(),
+                        expected = size,
+                        "GET body size mismatch."
+                    );
+                    return Err(InvalidResponse(if body.len() < *size {
+                        "short read"
+                    } else {
+                        "size mismatch"
+                    })
+                    .into());
+                }
+                if let Some(expected) = expected {
+                    let Some(offset) = offset else {
+                        return Err(InvalidResponse("missing data offset").into());
+                    };
+                    if expected.get(offset..offset + *size) != Some(&body[..]) {
+                        return Err(InvalidResponse("corrupted body").into());
+                    }
+                }
+            }
+            Ok(status)
         }
-        Endpoint::Post => {
-            client
-                .post(format!("https://{base_url}/post/{size}"))
-                .body(data)
+        Endpoint::Bytes => {
+            let response = client
+                .get(benchmark.url(base_url, &spec.path("/bytes/{size}")))
+                .headers(request_headers(request_id))
                 .send()
-                .await?
-                .error_for_status()?;
+                .await?;
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if status == StatusCode::BAD_REQUEST {
+                return size_exceeded(response, *size).await;
+            }
+            if is_success(status) {
+                check_content_type(&response, expect_content_type.as_deref())?;
+                let received = benchmark.read_body(response).await?.len();
+                if received != *size {
+                    debug!(received, expected = size, "Bytes body size mismatch.");
+                    return Err(InvalidResponse(if received < *size {
+                        "short read"
+                    } else {
+                        "size mismatch"
+                    })
+                    .into());
+                }
+            }
+            Ok(status)
         }
-        Endpoint::Websocket => {
+        Endpoint::Gzip | Endpoint::Deflate => {
+            let (template, encoding) = match endpoint {
+                Endpoint::Gzip => ("/gzip/{size}", "gzip"),
+                _ => ("/deflate/{size}", "deflate"),
+            };
             let response = client
-                .get(format!("wss://{base_url}/ws"))
-                .upgrade()
+                .get(benchmark.url(base_url, &spec.path(template)))
+                .headers(request_headers(request_id))
                 .send()
                 .await?;
-            let mut websocket = response.into_websocket().await?;
-            websocket
-                .send(reqwest_websocket::Message::Binary(data))
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if status == StatusCode::BAD_REQUEST {
+                return size_exceeded(response, *size).await;
+            }
+            if is_success(status) {
+                // The body must reach us still encoded, exactly as the service sent it.
+                if response
+                    .headers()
+                    .get(CONTENT_ENCODING)
+                    .is_none_or(|value| value != encoding)
+                {
+                    return Err(InvalidResponse("missing content encoding").into());
+                }
+                let body = benchmark.read_body(response).await?;
+                let mut decoded = Vec::with_capacity(*size);
+                let result = match endpoint {
+                    Endpoint::Gzip => GzDecoder::new(&body[..]).read_to_end(&mut decoded),
+                    _ => DeflateDecoder::new(&body[..]).read_to_end(&mut decoded),
+                };
+                if let Err(error) = result {
+                    debug!(?error, encoding, "Unable to decode body.");
+                    return Err(InvalidResponse("undecodable body").into());
+                }
+                if decoded.len() != *size {
+                    debug!(
+                        received = decoded.len(),
+                        expected = size,
+                        "Decoded body size mismatch."
+                    );
+                    return Err(InvalidResponse("size mismatch").into());
+                }
+                // The service encodes the start of its buffer, without any offset.
+                if let Some(expected) = expected
+                    && expected.get(..*size) != Some(&decoded[..])
+                {
+                    return Err(InvalidResponse("corrupted body").into());
+                }
+            }
+            Ok(status)
+        }
+        Endpoint::Ping => {
+            let response = client
+                .get(benchmark.url(base_url, &spec.path("/ping")))
+                .headers(request_headers(request_id))
+                .send()
                 .await?;
-            while let Some(message) = websocket.try_next().await? {
-                if let reqwest_websocket::Message::Binary(data) = message {
-                    if data.len() == size {
-                        break;
-                    }
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if is_success(status) && benchmark.read_body(response).await? != "pong" {
+                return Err(InvalidResponse("corrupted body").into());
+            }
+            Ok(status)
+        }
+        Endpoint::Trailers => {
+            let response = client
+                .get(benchmark.url(base_url, &spec.path("/trailers/{size}")))
+                .headers(request_headers(request_id))
+                .header(TE, "trailers")
+                .send()
+                .await?;
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if status == StatusCode::BAD_REQUEST {
+                return size_exceeded(response, *size).await;
+            }
+            if is_success(status) {
+                let body = reqwest::Body::from(response);
+                let collected = match benchmark.max_response_size {
+                    Some(max) => Limited::new(body, max as usize).collect().await.map_err(
+                        |error| -> color_eyre::Report {
+                            if error.is::<LengthLimitError>() {
+                                InvalidResponse("response too large").into()
+                            } else {
+                                eyre!(error)
+                            }
+                        },
+                    )?,
+                    None => body.collect().await?,
+                };
+                let checksum = collected
+                    .trailers()
+                    .and_then(|trailers| trailers.get("x-checksum"))
+                    .and_then(|checksum| checksum.to_str().ok())
+                    .and_then(|checksum| checksum.parse::<u32>().ok());
+                let body = collected.to_bytes();
+                if body.len() != *size {
+                    debug!(
+                        received = body.len(),
+                        expected = size,
+                        "Trailers body size mismatch."
+                    );
+                    return Err(InvalidResponse("size mismatch").into());
                 }
+                let Some(checksum) = checksum else {
+                    return Err(InvalidResponse("missing trailer").into());
+                };
+                if checksum != crc32fast::hash(&body) {
+                    return Err(InvalidResponse("checksum mismatch").into());
+                }
+            }
+            Ok(status)
+        }
+        Endpoint::Post if *multipart => {
+            let form = Form::new().part(
+                "file",
+                Part::stream_with_length(data.clone(), data.len() as u64).file_name("payload"),
+            );
+            let response = client
+                .post(benchmark.url(base_url, &spec.path("/upload/{size}")))
+                .headers(request_headers(request_id))
+                .multipart(form)
+                .send()
+                .await?;
+            benchmark.observe_session(base_url, response.headers());
+            if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                return over_limit(response, *size).await;
+            }
+            Ok(response.status())
+        }
+        Endpoint::Post if *post_info => {
+            let response = client
+                .post(benchmark.url(base_url, &spec.path("/post-info/{size}")))
+                .headers(request_headers(request_id))
+                .body(data.clone())
+                .send()
+                .await?;
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if status == StatusCode::PAYLOAD_TOO_LARGE {
+                return over_limit(response, *size).await;
+            }
+            if status == StatusCode::BAD_REQUEST
+                && let Ok(info) = serde_json::from_slice::<PostInfo>(&response.bytes().await?)
+            {
+                warn!(
+                    received = info.received,
+                    expected = info.expected,
+                    "POST body size mismatch."
+                );
             }
+            Ok(status)
+        }
+        Endpoint::Post => {
+            let response = client
+                .post(benchmark.url(base_url, &spec.path("/post/{size}")))
+                .headers(request_headers(request_id))
+                .body(benchmark.body(data))
+                .send()
+                .await?;
+            benchmark.observe_session(base_url, response.headers());
+            if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                return over_limit(response, *size).await;
+            }
+            Ok(response.status())
+        }
+        Endpoint::Websocket => {
+            // The upgrade through the tunnel can dominate, so time it apart from the echo.
+            let started = Instant::now();
+            let mut websocket =
+                match websocket_connect(benchmark, client, base_url, Some(request_id), recorder)
+                    .await?
+                {
+                    Ok(websocket) => websocket,
+                    Err(status) => return Ok(status),
+                };
+            recorder.record_phase("handshake", started.elapsed());
+            let started = Instant::now();
+            websocket_echo(&mut websocket, data.clone(), *size).await?;
+            recorder.record_phase("message", started.elapsed());
+            Ok(StatusCode::SWITCHING_PROTOCOLS)
+        }
+        Endpoint::Sse => {
+            let mut response = client
+                .get(benchmark.url(base_url, &spec.path("/sse")))
+                .headers(request_headers(request_id))
+                .send()
+                .await?;
+            benchmark.observe_session(base_url, response.headers());
+            let status = response.status();
+            if !is_success(status) {
+                return Ok(status);
+            }
+            let mut buffer = Vec::new();
+            let mut stream = SseStats::default();
+            let mut last_id: Option<u64> = None;
+            let mut last_event: Option<Instant> = None;
+            while stream.events < *sse_events {
+                let Some(chunk) = response.chunk().await? else {
+                    recorder.record_sse(&stream);
+                    return Err(eyre!("SSE stream ended after {} events.", stream.events));
+                };
+                buffer.extend_from_slice(&chunk);
+                // Events are delimited by an empty line.
+                while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+                    let event: Vec<u8> = buffer.drain(..end + 2).collect();
+                    // Skip keep-alive comments and any other event without a sequence number.
+                    let Some(id) = String::from_utf8_lossy(&event)
+                        .lines()
+                        .find_map(|line| line.strip_prefix("id:"))
+                        .and_then(|id| id.trim().parse::<u64>().ok())
+                    else {
+                        continue;
+                    };
+                    let now = Instant::now();
+                    stream.record_event(
+                        last_event.map(|last_event| now - last_event),
+                        last_id.map_or(0, |last_id| id.saturating_sub(last_id + 1)),
+                    );
+                    last_event = Some(now);
+                    last_id = Some(id);
+                }
+            }
+            recorder.record_sse(&stream);
+            Ok(status)
         }
     }
-    Ok(())
 }
@@ -1,48 +1,614 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 use clap::Parser;
-use sandhole_benchmark_measure::{Endpoint, EntrypointConfig, entrypoint};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use color_eyre::eyre::eyre;
+#[cfg(feature = "otel")]
+use sandhole_benchmark_measure::Otlp;
+use sandhole_benchmark_measure::{
+    AbortConfig, Auth, AutoscaleConfig, BaselineConfig, Endpoint, EntrypointConfig, Header,
+    HolBlockingConfig, PayloadPattern, RESULTS_TARGET, ReplayConfig, ResolveOverride, SlaConfig,
+    SocketConfig, Target, ThinkTime, analyze, entrypoint, selftest, sweep_sizes,
+};
+use tracing::{info, level_filters::LevelFilter};
+use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(clap::Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Config {
-    base_url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Without a subcommand, the flags of `run` are accepted as before.
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Only log errors and the final results line.
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more details; `-v` for debug logs, `-vv` for trace logs. `RUST_LOG` takes precedence.
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Number of Tokio runtime threads that the workers are spread across. Defaults to the
+    /// available parallelism; run with `RUST_LOG=debug` to see which thread each worker starts on.
+    #[arg(long, global = true, default_value_t = default_worker_threads())]
+    worker_threads: usize,
+
+    /// OTLP/HTTP endpoint to export traces to, such as `http://localhost:4318/v1/traces`. Each
+    /// request sends a `traceparent` header, so that the service's spans join its trace. Requires
+    /// building with the `otel` feature.
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run the benchmark.
+    Run(RunArgs),
+    /// Only round-trip a small GET, POST and WebSocket exchange with each target, to check the
+    /// setup before a run. Takes the same flags as `run`.
+    Selftest(RunArgs),
+    /// Recompute the results of a previous run from its `--records-file`.
+    Analyze {
+        /// Records file written by the run.
+        path: PathBuf,
+    },
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    /// URLs to benchmark, as `url` or `url=weight`.
+    #[arg(required_unless_present_any = ["target", "direct", "uds"])]
+    base_url: Vec<Target>,
+
+    /// Additional URL to benchmark, as `url` or `url=weight`. Can be repeated.
+    #[arg(long, short)]
+    target: Vec<Target>,
+
+    /// Benchmark the service directly over plain HTTP at `host:port`, bypassing the tunnel.
+    #[arg(long, conflicts_with_all = ["base_url", "target"])]
+    direct: Option<String>,
+
+    /// Benchmark the service directly over plain HTTP through the Unix socket at this path,
+    /// bypassing both TCP and the tunnel.
+    #[arg(long, conflicts_with_all = ["base_url", "target", "direct"])]
+    uds: Option<PathBuf>,
 
     #[arg(long, short, value_enum, default_value_t = Endpoint::Get)]
     endpoint: Endpoint,
 
+    /// Path to request instead of the endpoint's own (e.g. `/delay/{size}`), where `{size}` is
+    /// replaced by the size.
+    #[arg(long)]
+    path: Option<String>,
+
     #[arg(long, short, default_value_t = 10_000_000)]
     size: usize,
 
-    #[arg(long, short, default_value_t = 1)]
+    /// Run the benchmark once for each of these sizes, one after the other (e.g.
+    /// `1k,10k,100k,1M`), and log a summary of each.
+    #[arg(long, value_parser = parse_size, value_delimiter = ',', conflicts_with = "size")]
+    size_sweep: Vec<usize>,
+
+    #[arg(
+        long,
+        short,
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
     concurrency: usize,
 
+    /// Workers for the GET endpoint in a mixed workload, alongside the other `--*-concurrency`
+    /// pools. Overrides `--endpoint` and `--concurrency`.
+    #[arg(long, conflicts_with_all = ["endpoint", "concurrency"])]
+    get_concurrency: Option<usize>,
+
+    /// Workers for the POST endpoint in a mixed workload.
+    #[arg(long, conflicts_with_all = ["endpoint", "concurrency"])]
+    post_concurrency: Option<usize>,
+
+    /// Workers for the WebSocket endpoint in a mixed workload.
+    #[arg(long, conflicts_with_all = ["endpoint", "concurrency"])]
+    ws_concurrency: Option<usize>,
+
+    /// Workers for the SSE endpoint in a mixed workload.
+    #[arg(long, conflicts_with_all = ["endpoint", "concurrency"])]
+    sse_concurrency: Option<usize>,
+
+    /// Number of sequential requests made by each worker.
+    #[arg(long, short = 'n', default_value_t = 1)]
+    requests: usize,
+
+    /// How long each worker keeps sending requests, overriding `--requests`.
+    #[arg(long, short, value_parser = humantime::parse_duration)]
+    duration: Option<Duration>,
+
+    /// Target requests per second across all workers, correcting for coordinated omission.
+    #[arg(long, short)]
+    rate: Option<f64>,
+
+    /// Open this many new connections per second across all workers, each carrying a single
+    /// request, and report their setup latencies. Implies `--timing-breakdown`.
+    #[arg(long, conflicts_with_all = ["rate", "think_time"])]
+    connections_per_second: Option<f64>,
+
+    /// Maximum number of requests in flight across all workers.
+    #[arg(long)]
+    max_inflight: Option<usize>,
+
+    /// Random pause between each worker's requests, as `min:max` (e.g. `100ms:2s`).
+    #[arg(long, conflicts_with = "rate")]
+    think_time: Option<ThinkTime>,
+
+    /// Open a fresh connection for every request instead of reusing pooled connections.
+    #[arg(long)]
+    no_keepalive: bool,
+
+    /// Keep Nagle's algorithm enabled on client sockets. By default it's disabled, matching the
+    /// service's SSH connection. This only affects the hop to the tunnel's entrypoint.
+    #[arg(long)]
+    nagle: bool,
+
+    /// Local IP address to bind outgoing connections to, to send them through a specific network
+    /// interface.
+    #[arg(long)]
+    local_address: Option<IpAddr>,
+
+    /// Number of independent connections that the workers are spread across. With `--http2`,
+    /// each connection carries its workers' requests as concurrent streams.
+    #[arg(long, default_value_t = 1)]
+    connections: usize,
+
+    /// Use HTTP/2 for every request, without negotiating it first.
+    #[arg(long)]
+    http2: bool,
+
+    /// Use HTTP/3 over QUIC for every request, without negotiating it first. Requires building
+    /// with the `http3` feature, and a target that terminates QUIC itself.
+    #[arg(long, conflicts_with = "http2")]
+    http3: bool,
+
+    /// Limit each POST body upload to this many bytes per second, to simulate slow clients.
+    #[arg(long)]
+    max_upload_bps: Option<u64>,
+
+    /// Limit each GET body download to this many bytes per second, to simulate slow clients.
+    #[arg(long)]
+    max_download_bps: Option<u64>,
+
+    /// Fail requests whose response body grows past this many bytes, instead of buffering it
+    /// whole, to guard against pathological responses.
+    #[arg(long)]
+    max_response_size: Option<u64>,
+
+    /// Keep cookies set by responses and send them on later requests, shared by all workers.
+    #[arg(long)]
+    cookies: bool,
+
+    /// File to use as the POST/WebSocket body instead of random bytes. Overrides `--size`.
+    #[arg(long, short = 'b')]
+    body_file: Option<PathBuf>,
+
+    /// Content of the generated POST/WebSocket body, to control how well it compresses. Also used
+    /// to verify GET data, so it must match the service's pattern.
+    #[arg(long, value_enum, default_value = "random")]
+    payload_pattern: PayloadPattern,
+
+    /// Number of events to receive from each SSE connection.
+    #[arg(long, default_value_t = 10)]
+    sse_events: usize,
+
+    /// Keep one WebSocket connection open per worker for the whole run.
+    #[arg(long)]
+    websocket_persistent: bool,
+
+    /// Retries for WebSocket upgrades that fail before the handshake completes, with backoff.
+    #[arg(long, default_value_t = 3)]
+    websocket_retries: usize,
+
+    /// Send POST requests to the endpoint that reports received and expected sizes.
+    #[arg(long)]
+    post_info: bool,
+
+    /// Upload the POST body as the `file` part of a multipart form.
+    #[arg(long, conflicts_with = "post_info")]
+    multipart: bool,
+
+    /// Time the DNS, connect, TLS and time-to-first-byte phases, with a fresh connection per
+    /// request. Only supports GET and POST, and ignores proxies.
+    #[arg(long)]
+    timing_breakdown: bool,
+
+    /// Only open a fresh TLS connection and close it for each request, without sending any HTTP
+    /// request, to time the handshake through the tunnel on its own.
+    #[arg(long, conflicts_with_all = ["multipart", "websocket_persistent"])]
+    handshake_only: bool,
+
+    /// Send POST requests with `Expect: 100-continue`, and wait for the 100 Continue response
+    /// before sending the body. Uses a fresh connection per request, as `--timing-breakdown` does.
+    #[arg(long, conflicts_with_all = ["handshake_only", "multipart"])]
+    expect_continue: bool,
+
+    /// Write this many GET requests over a fresh connection before reading any response, to test
+    /// HTTP/1.1 pipelining through the tunnel, and check that the responses come back in order.
+    #[arg(
+        long,
+        conflicts_with_all = ["handshake_only", "expect_continue", "http2"],
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pipeline: Option<usize>,
+
     #[arg(long, short = 'C')]
     custom_ca_cert: Option<PathBuf>,
 
+    /// PEM certificate chain to present to the proxy for mutual TLS. Unrelated to the SSH key of
+    /// the tunnel.
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// PEM private key of `--client-cert`.
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
     #[arg(long, short)]
     host_ip: Option<SocketAddr>,
+
+    /// Pin a hostname to an address, as `host:port:addr`. Can be repeated.
+    #[arg(long)]
+    resolve: Vec<ResolveOverride>,
+
+    /// Proxy for all requests (http, https, socks5 or socks5h), optionally with credentials.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Extra header for every request, as `name: value`. Can be repeated.
+    #[arg(long, short = 'H')]
+    header: Vec<Header>,
+
+    /// User agent for every request.
+    #[arg(long, default_value = concat!("sandhole-benchmark-measure/", env!("CARGO_PKG_VERSION")))]
+    user_agent: String,
+
+    /// HTTP Basic credentials for every request, as `user:password`.
+    #[arg(long, value_parser = parse_basic, conflicts_with = "bearer")]
+    basic: Option<Auth>,
+
+    /// Bearer token for every request.
+    #[arg(long)]
+    bearer: Option<String>,
+
+    /// Double the concurrency on each stage until the p99 latency degrades, and report the knee.
+    #[arg(long)]
+    autoscale: bool,
+
+    /// Highest concurrency to try when autoscaling.
+    #[arg(long, default_value_t = 1024)]
+    autoscale_max_concurrency: usize,
+
+    /// Factor over the baseline p99 latency that stops autoscaling.
+    #[arg(long, default_value_t = 2.0)]
+    autoscale_threshold: f64,
+
+    /// Measure head-of-line blocking: run the GET requests on their own, then again alongside
+    /// large GET transfers of this size on the same HTTP/2 connections, and compare their p99.
+    #[arg(long, value_parser = parse_size, requires = "http2")]
+    hol_blocking_size: Option<usize>,
+
+    /// Number of large transfers in flight at once when measuring head-of-line blocking.
+    #[arg(long, default_value_t = 1)]
+    hol_blocking_concurrency: usize,
+
+    /// Replay a request log with `timestamp,method,path,size` rows instead of the endpoint's
+    /// requests, matching the recorded timing. Methods are either GET or POST, and the workers
+    /// send the entries in turn.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Factor to speed up the replayed timing by.
+    #[arg(long, default_value_t = 1.0, requires = "replay")]
+    speed: f64,
+
+    /// Fail if the p99 latency exceeds this many milliseconds.
+    #[arg(long)]
+    max_p99: Option<u64>,
+
+    /// Fail if the percentage of failed requests exceeds this value.
+    #[arg(long)]
+    max_error_rate: Option<f64>,
+
+    /// Fail if the successful requests per second are below this value.
+    #[arg(long)]
+    min_rps: Option<f64>,
+
+    /// Abort the run once the p99 latency over the abort window exceeds this many milliseconds.
+    #[arg(long, requires = "abort_window")]
+    abort_if_p99_over: Option<u64>,
+
+    /// Sliding window for evaluating the abort threshold.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    abort_window: Option<Duration>,
+
+    /// Write the results to this file as JSON.
+    #[arg(long)]
+    json: Option<PathBuf>,
+
+    /// Write the results to this file in the Prometheus text format, for node_exporter's textfile
+    /// collector.
+    #[arg(long)]
+    prom_file: Option<PathBuf>,
+
+    /// Write per-second requests, RPS and latency percentiles to this file as CSV.
+    #[arg(long)]
+    timeseries: Option<PathBuf>,
+
+    /// Write every request's outcome to this file as compact MessagePack, to recompute the
+    /// results later with `analyze`.
+    #[arg(long)]
+    records_file: Option<PathBuf>,
+
+    /// Log a summary of the requests completed over each interval while running, for long soak
+    /// tests.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    snapshot_interval: Option<Duration>,
+
+    /// Compare the results against a previous run's JSON file, failing on regressions.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Allowed regression from the baseline, in percent for RPS and p99 latency, or in percentage
+    /// points for the error rate.
+    #[arg(long, default_value_t = 5.0)]
+    baseline_tolerance: f64,
+
+    /// Stop the run once successful requests have sent and received this many payload bytes in
+    /// total, for metered connections.
+    #[arg(long)]
+    max_bytes: Option<u64>,
+
+    /// Stop and exit with an error on the first failed request, instead of tallying failures.
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Count 4xx and 5xx responses as successful, still telling them apart in the status
+    /// distribution, to measure the latency of endpoints that are expected to return errors.
+    #[arg(long)]
+    no_error_for_status: bool,
+
+    /// How long after a detected tunnel reconnect requests are summarized apart from the steady
+    /// state. Reconnects are detected from requests failing without a response, or from a change
+    /// in the service's `X-Tunnel-Session` header.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+    recovery_window: Duration,
+
+    /// Round-trip one GET, POST and WebSocket exchange with each target before the run, failing
+    /// fast if any of them doesn't work.
+    #[arg(long)]
+    selftest: bool,
+
+    /// Wait until this RFC 3339 timestamp (e.g. `2026-01-01T12:00:00Z`) to start the load, to
+    /// synchronize several instances.
+    #[arg(long, value_parser = humantime::parse_rfc3339_weak)]
+    start_at: Option<SystemTime>,
+
+    /// Seed that the service used to generate its GET data.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Record GET responses without this `Content-Type` (e.g. `application/octet-stream`) as
+    /// invalid, to catch headers rewritten by the tunnel.
+    #[arg(long)]
+    expect_content_type: Option<String>,
+
+    /// Verify GET responses against the data generated from the seed.
+    #[arg(long, requires = "seed")]
+    verify: bool,
+
+    /// Also log the effective configuration as JSON at startup.
+    #[arg(long)]
+    config_json: bool,
 }
 
-#[tokio::main]
-async fn main() -> color_eyre::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::builder()
-                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .with(tracing_subscriber::fmt::Layer::default().compact())
-        .init();
-    color_eyre::install()?;
-    let config = Config::parse();
-    entrypoint(EntrypointConfig {
-        base_url: config.base_url,
-        endpoint: config.endpoint,
-        size: config.size,
-        concurrency: config.concurrency,
-        custom_ca_cert: config.custom_ca_cert,
-        host_ip: config.host_ip,
+/// Parses a size in bytes, with an optional `k`, `M` or `G` suffix for powers of 1000.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (number, multiplier) = match value.strip_suffix(['k', 'K']) {
+        Some(number) => (number, 1_000),
+        None => match value.strip_suffix('M') {
+            Some(number) => (number, 1_000_000),
+            None => match value.strip_suffix('G') {
+                Some(number) => (number, 1_000_000_000),
+                None => (value, 1),
+            },
+        },
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {value}"))
+}
+
+fn parse_basic(value: &str) -> Result<Auth, String> {
+    let (user, password) = value
+        .split_once(':')
+        .ok_or_else(|| "expected user:password".to_string())?;
+    Ok(Auth::Basic {
+        user: user.to_string(),
+        password: password.to_string(),
     })
-    .await
+}
+
+fn default_worker_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+fn main() -> color_eyre::Result<()> {
+    let config = Config::parse();
+    let level = match (config.quiet, config.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let filter = if config.quiet {
+        filter.add_directive(format!("{RESULTS_TARGET}=info").parse()?)
+    } else {
+        filter
+    };
+    if config.otlp_endpoint.is_some() && !cfg!(feature = "otel") {
+        return Err(eyre!(
+            "OpenTelemetry export requires building measure with the `otel` feature."
+        ));
+    }
+    #[cfg(feature = "otel")]
+    let otlp = config.otlp_endpoint.as_deref().map(Otlp::new).transpose()?;
+    let registry = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::Layer::default()
+            .compact()
+            .with_filter(filter),
+    );
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otlp.as_ref().map(|otlp| otlp.layer()));
+    registry.init();
+    color_eyre::install()?;
+    info!(worker_threads = config.worker_threads, "Starting runtime.");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .enable_all()
+        .build()?;
+    let (mut run, selftest_only) = match config.command {
+        None => (config.run, false),
+        Some(Command::Run(run)) => (run, false),
+        Some(Command::Selftest(run)) => (run, true),
+        Some(Command::Analyze { path }) => {
+            analyze(&path)?;
+            return Ok(());
+        }
+    };
+    if selftest_only {
+        return runtime.block_on(selftest(run.into_entrypoint_config()));
+    }
+    if !run.size_sweep.is_empty() {
+        let sizes = std::mem::take(&mut run.size_sweep);
+        for stats in runtime.block_on(sweep_sizes(run.into_entrypoint_config(), sizes))? {
+            stats.check()?;
+        }
+        return Ok(());
+    }
+    // The results are already logged, so only their SLA and baseline checks are left.
+    runtime
+        .block_on(entrypoint(run.into_entrypoint_config()))?
+        .check()
+}
+
+impl RunArgs {
+    fn into_entrypoint_config(self) -> EntrypointConfig {
+        EntrypointConfig {
+            targets: self.base_url.into_iter().chain(self.target).collect(),
+            direct: self.direct,
+            uds: self.uds,
+            endpoint: self.endpoint,
+            path: self.path,
+            size: self.size,
+            concurrency: self.concurrency,
+            mix: [
+                (Endpoint::Get, self.get_concurrency),
+                (Endpoint::Post, self.post_concurrency),
+                (Endpoint::Websocket, self.ws_concurrency),
+                (Endpoint::Sse, self.sse_concurrency),
+            ]
+            .into_iter()
+            .filter_map(|(endpoint, concurrency)| Some((endpoint, concurrency?)))
+            .collect(),
+            requests: self.requests,
+            duration: self.duration,
+            rate: self.rate,
+            connections_per_second: self.connections_per_second,
+            max_inflight: self.max_inflight,
+            think_time: self.think_time,
+            keepalive: !self.no_keepalive,
+            connections: self.connections,
+            max_upload_bps: self.max_upload_bps,
+            max_download_bps: self.max_download_bps,
+            max_response_size: self.max_response_size,
+            http2: self.http2,
+            http3: self.http3,
+            socket: SocketConfig {
+                nodelay: !self.nagle,
+                local_address: self.local_address,
+            },
+            cookies: self.cookies,
+            body_file: self.body_file,
+            payload_pattern: self.payload_pattern,
+            sse_events: self.sse_events,
+            websocket_persistent: self.websocket_persistent,
+            websocket_retries: self.websocket_retries,
+            post_info: self.post_info,
+            multipart: self.multipart,
+            timing_breakdown: self.timing_breakdown,
+            handshake_only: self.handshake_only,
+            expect_continue: self.expect_continue,
+            pipeline: self.pipeline,
+            custom_ca_cert: self.custom_ca_cert,
+            client_cert: self.client_cert,
+            client_key: self.client_key,
+            host_ip: self.host_ip,
+            resolve: self.resolve,
+            proxy: self.proxy,
+            headers: self.header,
+            user_agent: self.user_agent,
+            auth: self.basic.or(self.bearer.map(Auth::Bearer)),
+            hol_blocking: self.hol_blocking_size.map(|large_size| HolBlockingConfig {
+                large_size,
+                large_concurrency: self.hol_blocking_concurrency,
+            }),
+            autoscale: self.autoscale.then_some(AutoscaleConfig {
+                max_concurrency: self.autoscale_max_concurrency,
+                threshold: self.autoscale_threshold,
+            }),
+            replay: self.replay.map(|path| ReplayConfig {
+                path,
+                speed: self.speed,
+            }),
+            sla: SlaConfig {
+                max_p99: self.max_p99.map(Duration::from_millis),
+                max_error_rate: self.max_error_rate,
+                min_rps: self.min_rps,
+            },
+            abort: self
+                .abort_if_p99_over
+                .zip(self.abort_window)
+                .map(|(max_p99, window)| AbortConfig {
+                    max_p99: Duration::from_millis(max_p99),
+                    window,
+                }),
+            json: self.json,
+            prom_file: self.prom_file,
+            timeseries: self.timeseries,
+            records_file: self.records_file,
+            snapshot_interval: self.snapshot_interval,
+            baseline: self.baseline.map(|path| BaselineConfig {
+                path,
+                tolerance: self.baseline_tolerance,
+            }),
+            max_bytes: self.max_bytes,
+            fail_fast: self.fail_fast,
+            error_for_status: !self.no_error_for_status,
+            recovery_window: self.recovery_window,
+            selftest: self.selftest,
+            start_at: self.start_at,
+            seed: self.seed,
+            expect_content_type: self.expect_content_type,
+            verify: self.verify,
+            config_json: self.config_json,
+        }
+    }
 }
@@ -0,0 +1,375 @@
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{WrapErr, eyre};
+use reqwest::{StatusCode, header::HeaderMap};
+use rustls::{ClientConfig, pki_types::ServerName};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+};
+use tokio_rustls::{TlsConnector, client::TlsStream};
+
+use crate::{Endpoint, ResolveOverride, SocketConfig, stats::Phases};
+
+/* Low-level connections */
+
+/// How long to wait for a 100 Continue response before sending the body anyway, as curl does.
+const EXPECT_CONTINUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A connector that opens fresh TLS connections by hand, timing each phase of the setup. Unlike
+/// `reqwest`, it always uses a new HTTP/1.1 connection per request and ignores proxies.
+pub(crate) struct Probe {
+    connector: TlsConnector,
+    host_ip: Option<SocketAddr>,
+    resolve: Vec<ResolveOverride>,
+    /// Extra header lines, already formatted for the request head.
+    headers: String,
+    socket: SocketConfig,
+    /// Whether POST requests wait for a 100 Continue response before sending the body.
+    expect_continue: bool,
+}
+
+impl Probe {
+    pub(crate) fn new(
+        tls_config: ClientConfig,
+        host_ip: Option<SocketAddr>,
+        resolve: Vec<ResolveOverride>,
+        headers: &HeaderMap,
+        socket: SocketConfig,
+        expect_continue: bool,
+    ) -> Self {
+        Self {
+            connector: TlsConnector::from(Arc::new(tls_config)),
+            host_ip,
+            resolve,
+            headers: headers
+                .iter()
+                .map(|(name, value)| {
+                    format!("{name}: {}\r\n", String::from_utf8_lossy(value.as_bytes()))
+                })
+                .collect(),
+            socket,
+            expect_continue,
+        }
+    }
+
+    /// Resolves the host, connects and completes the TLS handshake, recording how long each
+    /// phase took.
+    pub(crate) async fn connect(
+        &self,
+        base_url: &str,
+        phases: &mut Phases,
+    ) -> color_eyre::Result<TlsStream<TcpStream>> {
+        let authority = base_url
+            .split_once('/')
+            .map_or(base_url, |(authority, _)| authority);
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().wrap_err_with(|| "Invalid port.")?),
+            None => (authority, 443),
+        };
+        let started = Instant::now();
+        let addr = if let Some(entry) = self.resolve.iter().find(|entry| entry.host == host) {
+            entry.addr
+        } else if let Some(host_ip) = self.host_ip {
+            host_ip
+        } else {
+            tokio::net::lookup_host((host, port))
+                .await?
+                .next()
+                .ok_or_else(|| eyre!("No addresses found for {host}."))?
+        };
+        phases.dns = started.elapsed();
+        let started = Instant::now();
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        if let Some(local_address) = self.socket.local_address {
+            socket
+                .bind(SocketAddr::new(local_address, 0))
+                .wrap_err_with(|| format!("Unable to bind to {local_address}."))?;
+        }
+        let socket = socket.connect(addr).await?;
+        socket.set_nodelay(self.socket.nodelay)?;
+        phases.connect = started.elapsed();
+        let started = Instant::now();
+        let server_name = ServerName::try_from(host.to_string())?;
+        let stream = self.connector.connect(server_name, socket).await?;
+        phases.tls = started.elapsed();
+        Ok(stream)
+    }
+
+    /// Opens a fresh TLS connection and closes it right away, without sending a request, to time
+    /// the connection setup on its own.
+    pub(crate) async fn handshake(&self, base_url: &str) -> color_eyre::Result<Phases> {
+        let mut phases = Phases::default();
+        let mut stream = self.connect(base_url, &mut phases).await?;
+        stream.shutdown().await?;
+        Ok(phases)
+    }
+
+    /// Makes a single HTTP/1.1 request over a fresh connection, recording the time to the first
+    /// byte of the response after the connection is set up.
+    pub(crate) async fn request(
+        &self,
+        base_url: &str,
+        endpoint: Endpoint,
+        data: &[u8],
+        request_path: &str,
+        request_id: &str,
+    ) -> color_eyre::Result<(StatusCode, Phases)> {
+        let mut phases = Phases::default();
+        let mut stream = self.connect(base_url, &mut phases).await?;
+        let (host, prefix) = match base_url.split_once('/') {
+            Some((host, prefix)) => (host, format!("/{prefix}")),
+            None => (base_url, String::new()),
+        };
+        let head = match endpoint {
+            Endpoint::Get => format!(
+                concat!(
+                    "GET {prefix}{request_path} HTTP/1.1\r\nHost: {host}\r\n{headers}",
+                    "X-Request-Id: {request_id}\r\n",
+                    "Connection: close\r\n\r\n"
+                ),
+                prefix = prefix,
+                request_path = request_path,
+                host = host,
+                headers = self.headers,
+                request_id = request_id
+            ),
+            Endpoint::Post => format!(
+                concat!(
+                    "POST {prefix}{request_path} HTTP/1.1\r\nHost: {host}\r\n{headers}",
+                    "X-Request-Id: {request_id}\r\n{expect}",
+                    "Content-Length: {length}\r\nConnection: close\r\n\r\n"
+                ),
+                prefix = prefix,
+                request_path = request_path,
+                host = host,
+                headers = self.headers,
+                request_id = request_id,
+                expect = if self.expect_continue {
+                    "Expect: 100-continue\r\n"
+                } else {
+                    ""
+                },
+                length = data.len()
+            ),
+            _ => return Err(eyre!("Timing breakdown only supports GET and POST.")),
+        };
+        let started = Instant::now();
+        stream.write_all(head.as_bytes()).await?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut response_head = Vec::new();
+        if endpoint == Endpoint::Post {
+            let send_body = if self.expect_continue {
+                stream.flush().await?;
+                let interim = read_interim(&mut stream, &mut buffer, &mut response_head);
+                match tokio::time::timeout(EXPECT_CONTINUE_TIMEOUT, interim).await {
+                    Ok(continued) => {
+                        let continued = continued?;
+                        phases.continued = Some(continued);
+                        // Any other response is final, so the body isn't wanted anymore.
+                        continued
+                    }
+                    // The header may have been dropped on the way, so the server is still waiting.
+                    Err(_) => {
+                        phases.continued = Some(false);
+                        true
+                    }
+                }
+            } else {
+                true
+            };
+            if send_body {
+                stream.write_all(data).await?;
+            }
+        }
+        stream.flush().await?;
+        let mut read = read_until_close(&mut stream, &mut buffer).await?;
+        phases.ttfb = started.elapsed();
+        // Read until the status line is complete, then drain the rest of the response.
+        while read > 0 {
+            if !response_head.contains(&b'\n') {
+                response_head.extend_from_slice(&buffer[..read]);
+            }
+            read = read_until_close(&mut stream, &mut buffer).await?;
+        }
+        let status = String::from_utf8_lossy(&response_head)
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .ok_or_else(|| eyre!("Malformed HTTP response."))?;
+        Ok((status, phases))
+    }
+
+    /// Writes a GET request for each of the request IDs over a fresh connection before reading
+    /// any response, as HTTP/1.1 pipelining does, then reads the responses one after the other.
+    pub(crate) async fn pipeline(
+        &self,
+        base_url: &str,
+        request_path: &str,
+        request_ids: &[String],
+    ) -> color_eyre::Result<Pipelined> {
+        let mut phases = Phases::default();
+        let mut stream = self.connect(base_url, &mut phases).await?;
+        let (host, prefix) = match base_url.split_once('/') {
+            Some((host, prefix)) => (host, format!("/{prefix}")),
+            None => (base_url, String::new()),
+        };
+        let heads: String = request_ids
+            .iter()
+            .enumerate()
+            .map(|(i, request_id)| {
+                format!(
+                    concat!(
+                        "GET {prefix}{request_path} HTTP/1.1\r\nHost: {host}\r\n{headers}",
+                        "X-Request-Id: {request_id}\r\n",
+                        "Connection: {connection}\r\n\r\n"
+                    ),
+                    prefix = prefix,
+                    request_path = request_path,
+                    host = host,
+                    headers = self.headers,
+                    request_id = request_id,
+                    connection = if i + 1 == request_ids.len() {
+                        "close"
+                    } else {
+                        "keep-alive"
+                    }
+                )
+            })
+            .collect();
+        let started = Instant::now();
+        stream.write_all(heads.as_bytes()).await?;
+        stream.flush().await?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut pending = Vec::new();
+        let mut responses = Vec::with_capacity(request_ids.len());
+        let mut in_order = true;
+        for expected in request_ids {
+            let (status, request_id) =
+                read_pipelined(&mut stream, &mut buffer, &mut pending).await?;
+            if responses.is_empty() {
+                phases.ttfb = started.elapsed();
+            }
+            in_order &= request_id.as_ref() == Some(expected);
+            responses.push((status, Instant::now()));
+        }
+        Ok(Pipelined {
+            responses,
+            in_order,
+            phases,
+        })
+    }
+}
+
+/// Responses to pipelined requests, in the order that they arrived.
+pub(crate) struct Pipelined {
+    /// Status of each response, and when it was received.
+    pub(crate) responses: Vec<(StatusCode, Instant)>,
+    /// Whether the responses came back in the same order as the requests.
+    pub(crate) in_order: bool,
+    pub(crate) phases: Phases,
+}
+
+/// Reads from the stream, treating a missing TLS close_notify as the end of the stream, since
+/// servers commonly close without one.
+async fn read_until_close(
+    stream: &mut TlsStream<TcpStream>,
+    buffer: &mut [u8],
+) -> color_eyre::Result<usize> {
+    match stream.read(buffer).await {
+        Ok(read) => Ok(read),
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(0),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Reads until `pending` holds a complete response head, and returns where the head ends.
+async fn read_head(
+    stream: &mut TlsStream<TcpStream>,
+    buffer: &mut [u8],
+    pending: &mut Vec<u8>,
+) -> color_eyre::Result<usize> {
+    loop {
+        if let Some(end) = pending.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(end + 4);
+        }
+        let read = read_until_close(stream, buffer).await?;
+        if read == 0 {
+            return Err(eyre!("Connection closed before the response head."));
+        }
+        pending.extend_from_slice(&buffer[..read]);
+    }
+}
+
+/// Reads the next response on a pipelined connection and discards its body, returning its
+/// status and echoed request ID. Anything read past it is left in `pending`.
+async fn read_pipelined(
+    stream: &mut TlsStream<TcpStream>,
+    buffer: &mut [u8],
+    pending: &mut Vec<u8>,
+) -> color_eyre::Result<(StatusCode, Option<String>)> {
+    let end = read_head(stream, buffer, pending).await?;
+    let head = String::from_utf8_lossy(&pending[..end]).into_owned();
+    pending.drain(..end);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .ok_or_else(|| eyre!("Malformed HTTP response."))?;
+    let mut length = None;
+    let mut request_id = None;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.trim().parse::<usize>().ok();
+        } else if name.eq_ignore_ascii_case("x-request-id") {
+            request_id = Some(value.trim().to_string());
+        }
+    }
+    // Without a length, there's no telling where the next response starts.
+    let mut remaining =
+        length.ok_or_else(|| eyre!("Pipelined response without a Content-Length."))?;
+    let buffered = remaining.min(pending.len());
+    pending.drain(..buffered);
+    remaining -= buffered;
+    while remaining > 0 {
+        let read = read_until_close(stream, buffer).await?;
+        if read == 0 {
+            return Err(eyre!("Connection closed in the middle of a response."));
+        }
+        let body = read.min(remaining);
+        remaining -= body;
+        pending.extend_from_slice(&buffer[body..read]);
+    }
+    Ok((status, request_id))
+}
+
+/// Reads the first response head after an `Expect: 100-continue` request, and returns whether
+/// it's a 100 Continue. Anything read past it is left in `response`, which otherwise holds the
+/// start of the final response.
+async fn read_interim(
+    stream: &mut TlsStream<TcpStream>,
+    buffer: &mut [u8],
+    response: &mut Vec<u8>,
+) -> color_eyre::Result<bool> {
+    let end = read_head(stream, buffer, response).await?;
+    let continued = String::from_utf8_lossy(&response[..end])
+        .split_whitespace()
+        .nth(1)
+        == Some("100");
+    if continued {
+        response.drain(..end);
+    }
+    Ok(continued)
+}
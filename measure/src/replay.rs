@@ -0,0 +1,120 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use color_eyre::eyre::{WrapErr, eyre};
+use rand::RngCore;
+
+use crate::{Endpoint, RequestSpec};
+
+/* Request log replay */
+
+/// A request from a recorded log, sent at the same offset from the start of the run.
+pub(crate) struct ReplayEntry {
+    offset: Duration,
+    endpoint: Endpoint,
+    path: String,
+    size: usize,
+}
+
+/// Parses a request log with `timestamp,method,path,size` rows, where timestamps are in seconds
+/// and methods are either GET or POST. Blank lines, comments starting with `#` and a header row
+/// are skipped.
+pub(crate) fn parse_replay(contents: &str) -> color_eyre::Result<Vec<ReplayEntry>> {
+    let mut rows = Vec::new();
+    let mut first_row = true;
+    for (index, line) in contents.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Only the first row can be a header, even when comments come before it.
+        let header = std::mem::replace(&mut first_row, false);
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [timestamp, method, path, size] = fields[..] else {
+            return Err(eyre!("Line {number}: expected timestamp,method,path,size."));
+        };
+        let Some(timestamp) = timestamp
+            .parse::<f64>()
+            .ok()
+            .filter(|parsed| parsed.is_finite())
+        else {
+            if header {
+                continue;
+            }
+            return Err(eyre!("Line {number}: invalid timestamp {timestamp}."));
+        };
+        let endpoint = match method.to_ascii_uppercase().as_str() {
+            "GET" => Endpoint::Get,
+            "POST" => Endpoint::Post,
+            _ => return Err(eyre!("Line {number}: unsupported method {method}.")),
+        };
+        let size = size
+            .parse::<usize>()
+            .wrap_err_with(|| format!("Line {number}: invalid size {size}."))?;
+        rows.push((number, timestamp, endpoint, path.to_string(), size));
+    }
+    let start = rows
+        .iter()
+        .map(|(_, timestamp, ..)| *timestamp)
+        .fold(f64::INFINITY, f64::min);
+    rows.into_iter()
+        .map(|(number, timestamp, endpoint, path, size)| {
+            let offset = Duration::try_from_secs_f64(timestamp - start).wrap_err_with(|| {
+                format!("Line {number}: timestamp {timestamp} is out of range.")
+            })?;
+            Ok::<_, color_eyre::Report>(ReplayEntry {
+                offset,
+                endpoint,
+                path,
+                size,
+            })
+        })
+        .collect()
+}
+
+/// A request log being replayed, whose entries the workers take in order of their offsets.
+pub(crate) struct Replay {
+    entries: Vec<ReplayEntry>,
+    next: AtomicUsize,
+    speed: f64,
+    /// Random payload for the largest entry, sliced for the others.
+    data: Bytes,
+}
+
+impl Replay {
+    pub(crate) fn new(mut entries: Vec<ReplayEntry>, speed: f64) -> Self {
+        entries.sort_by_key(|entry| entry.offset);
+        let max_size = entries.iter().map(|entry| entry.size).max().unwrap_or(0);
+        let mut data = vec![0u8; max_size];
+        rand::rng().fill_bytes(&mut data);
+        Replay {
+            entries,
+            next: AtomicUsize::new(0),
+            speed,
+            data: Bytes::from(data),
+        }
+    }
+
+    /// Takes the next entry, returning its endpoint, its request with `{size}` in the path
+    /// replaced by its size, and when it's due: its recorded offset from the given start, divided
+    /// by the speed factor.
+    pub(crate) fn next(&self, started: Instant) -> Option<(Endpoint, RequestSpec<'_>, Instant)> {
+        let entry = self
+            .entries
+            .get(self.next.fetch_add(1, Ordering::Relaxed))?;
+        let spec = RequestSpec {
+            path: Some(&entry.path),
+            data: self.data.slice(..entry.size),
+            size: entry.size,
+        };
+        Some((
+            entry.endpoint,
+            spec,
+            started + entry.offset.div_f64(self.speed),
+        ))
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{Endpoint, StageStats};
+
+/* Machine-readable results */
+
+/// Summary of a benchmark run, written as JSON and read back as a baseline for later runs.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Report {
+    endpoint: String,
+    concurrency: usize,
+    requests: usize,
+    failures: usize,
+    /// Fraction of failed requests, from 0 to 1.
+    error_rate: f64,
+    rps: f64,
+    p50_ms: f64,
+    p99_ms: f64,
+    elapsed_ms: f64,
+    statuses: BTreeMap<u16, usize>,
+    /// Number of requests that can be retried for each number of attempts they took.
+    #[serde(default)]
+    attempts: BTreeMap<usize, usize>,
+    /// Fraction of the allowed retries that were used, from 0 to 1.
+    #[serde(default)]
+    retry_budget_consumed: Option<f64>,
+    /// Payload bytes transferred by successful requests.
+    #[serde(default)]
+    bytes: u64,
+}
+
+impl Report {
+    /// Summarizes the stats, where each request that can be retried is allowed up to
+    /// `max_retries` retries, and successful requests transferred `bytes` of payload.
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        stats: &StageStats,
+        max_retries: usize,
+        bytes: u64,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            concurrency: stats.concurrency,
+            requests: stats.requests(),
+            failures: stats.failures(),
+            error_rate: stats.error_rate(),
+            rps: stats.rps(),
+            p50_ms: stats.percentile(50.0).as_secs_f64() * 1000.0,
+            p99_ms: stats.percentile(99.0).as_secs_f64() * 1000.0,
+            elapsed_ms: stats.elapsed.as_secs_f64() * 1000.0,
+            statuses: stats.statuses.clone(),
+            attempts: stats.attempts.clone(),
+            retry_budget_consumed: stats.retry_budget_consumed(max_retries),
+            bytes,
+        }
+    }
+
+    /// Logs the differences from a baseline run, and returns a description of each metric that
+    /// regressed by more than the tolerance (in percent, or percentage points for the error rate).
+    pub(crate) fn compare(&self, baseline: &Report, tolerance: f64) -> Vec<String> {
+        let rps_change = percent_change(baseline.rps, self.rps);
+        let p99_change = percent_change(baseline.p99_ms, self.p99_ms);
+        let error_rate_delta = (self.error_rate - baseline.error_rate) * 100.0;
+        info!(
+            rps_delta = format!("{:+.2}", self.rps - baseline.rps),
+            rps_change = format!("{rps_change:+.2}%"),
+            p99_delta = format!("{:+.2}ms", self.p99_ms - baseline.p99_ms),
+            p99_change = format!("{p99_change:+.2}%"),
+            error_rate_delta = format!("{error_rate_delta:+.2}pp"),
+            "Compared against baseline."
+        );
+        let mut regressions = Vec::new();
+        if rps_change < -tolerance {
+            regressions.push(format!(
+                "RPS dropped by {:.2}%, beyond the tolerance of {tolerance:.2}%",
+                -rps_change
+            ));
+        }
+        if p99_change > tolerance {
+            regressions.push(format!(
+                "p99 latency rose by {p99_change:.2}%, beyond the tolerance of {tolerance:.2}%"
+            ));
+        }
+        if error_rate_delta > tolerance {
+            regressions.push(format!(
+                "error rate rose by {error_rate_delta:.2} points, beyond the tolerance of \
+                 {tolerance:.2}"
+            ));
+        }
+        regressions
+    }
+
+    /// Renders the results in the Prometheus text exposition format, for node_exporter's textfile
+    /// collector. Every metric is labelled with the endpoint, size and concurrency of the run.
+    pub(crate) fn to_prometheus(&self, size: usize) -> String {
+        let labels = format!(
+            "endpoint=\"{}\",size=\"{size}\",concurrency=\"{}\"",
+            self.endpoint, self.concurrency
+        );
+        let metrics: [(&str, &str, &str, &str, f64); 7] = [
+            (
+                "sandhole_benchmark_requests_total",
+                "counter",
+                "Requests sent during the run.",
+                "",
+                self.requests as f64,
+            ),
+            (
+                "sandhole_benchmark_failures_total",
+                "counter",
+                "Requests that failed during the run.",
+                "",
+                self.failures as f64,
+            ),
+            (
+                "sandhole_benchmark_error_ratio",
+                "gauge",
+                "Fraction of failed requests, from 0 to 1.",
+                "",
+                self.error_rate,
+            ),
+            (
+                "sandhole_benchmark_requests_per_second",
+                "gauge",
+                "Successful requests per second.",
+                "",
+                self.rps,
+            ),
+            (
+                "sandhole_benchmark_latency_seconds",
+                "gauge",
+                "Request latency percentiles.",
+                "quantile=\"0.5\",",
+                self.p50_ms / 1000.0,
+            ),
+            (
+                "sandhole_benchmark_latency_seconds",
+                "gauge",
+                "Request latency percentiles.",
+                "quantile=\"0.99\",",
+                self.p99_ms / 1000.0,
+            ),
+            (
+                "sandhole_benchmark_bytes_total",
+                "counter",
+                "Payload bytes transferred by successful requests.",
+                "",
+                self.bytes as f64,
+            ),
+        ];
+        let mut output = String::new();
+        let mut last_name = "";
+        for (name, kind, help, extra_labels, value) in metrics {
+            // Each metric family's metadata must only appear once.
+            if name != last_name {
+                let _ = writeln!(output, "# HELP {name} {help}");
+                let _ = writeln!(output, "# TYPE {name} {kind}");
+                last_name = name;
+            }
+            let _ = writeln!(output, "{name}{{{extra_labels}{labels}}} {value}");
+        }
+        output
+    }
+}
+
+/// Relative change from `before` to `after`, in percent.
+pub(crate) fn percent_change(before: f64, after: f64) -> f64 {
+    if before == 0.0 {
+        0.0
+    } else {
+        (after - before) / before * 100.0
+    }
+}
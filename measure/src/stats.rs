@@ -0,0 +1,664 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{Display, Write},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::eyre;
+use reqwest::StatusCode;
+
+use crate::Endpoint;
+
+/* Benchmark statistics */
+
+/// Error for a response that was received but failed validation, tallied under its kind.
+#[derive(Debug)]
+pub(crate) struct InvalidResponse(pub(crate) &'static str);
+
+impl Display for InvalidResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid response ({})", self.0)
+    }
+}
+
+impl std::error::Error for InvalidResponse {}
+
+/// Error for a WebSocket connection that closed before the echo was received, with its close
+/// code. Connections that end without a close frame use 1006, as per RFC 6455.
+#[derive(Debug)]
+pub(crate) struct WebsocketClosed(pub(crate) u16);
+
+impl Display for WebsocketClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WebSocket closed with code {}", self.0)
+    }
+}
+
+impl std::error::Error for WebsocketClosed {}
+
+/// Time spent in each phase of a request over a fresh connection.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Phases {
+    pub(crate) dns: Duration,
+    pub(crate) connect: Duration,
+    pub(crate) tls: Duration,
+    pub(crate) ttfb: Duration,
+    /// Whether a 100 Continue response came before the body was sent, if the request asked for
+    /// one with `Expect: 100-continue`.
+    pub(crate) continued: Option<bool>,
+}
+
+impl Phases {
+    fn iter(&self) -> [(&'static str, Duration); 5] {
+        [
+            ("dns", self.dns),
+            ("connect", self.connect),
+            ("tls", self.tls),
+            // Everything before the request can be sent, i.e. the cost of a new connection.
+            ("setup", self.dns + self.connect + self.tls),
+            ("ttfb", self.ttfb),
+        ]
+    }
+}
+
+/// Latency at the given percentile (from 0 to 100) of sorted latencies, using the nearest-rank
+/// method.
+fn percentile_of(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Formats a latency with the unit that fits its magnitude, from nanoseconds to seconds, so that
+/// sub-millisecond latencies keep their resolution.
+pub(crate) fn format_latency(latency: Duration) -> String {
+    let nanos = latency.as_nanos();
+    if nanos < 1_000 {
+        format!("{nanos}ns")
+    } else if nanos < 1_000_000 {
+        format!("{:.2}µs", nanos as f64 / 1e3)
+    } else if nanos < 1_000_000_000 {
+        format!("{:.2}ms", nanos as f64 / 1e6)
+    } else {
+        format!("{:.2}s", latency.as_secs_f64())
+    }
+}
+
+/// Events received over SSE streams, and the gaps between consecutive events of a stream.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SseStats {
+    pub events: usize,
+    /// Events missing from the sequence numbers.
+    pub dropped: u64,
+    pub max_gap: Duration,
+    total_gap: Duration,
+    gaps: u32,
+}
+
+impl SseStats {
+    /// Records an event, after the given gap since the previous event of its stream if any, and
+    /// skipping over the given number of sequence numbers.
+    pub(crate) fn record_event(&mut self, gap: Option<Duration>, skipped: u64) {
+        self.events += 1;
+        self.dropped += skipped;
+        if let Some(gap) = gap {
+            self.max_gap = self.max_gap.max(gap);
+            self.total_gap += gap;
+            self.gaps = self.gaps.saturating_add(1);
+        }
+    }
+
+    /// Mean gap between consecutive events of a stream.
+    pub fn mean_gap(&self) -> Duration {
+        self.total_gap
+            .checked_div(self.gaps)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    fn merge(&mut self, other: &SseStats) {
+        self.events += other.events;
+        self.dropped += other.dropped;
+        self.max_gap = self.max_gap.max(other.max_gap);
+        self.total_gap += other.total_gap;
+        self.gaps = self.gaps.saturating_add(other.gaps);
+    }
+}
+
+/// Accumulator for the results of requests made by a single worker.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Recorder {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    requests: usize,
+    successes: usize,
+    connection_errors: usize,
+    dropped_connections: usize,
+    invalid: BTreeMap<&'static str, usize>,
+    synthesized: usize,
+    phases: BTreeMap<&'static str, Vec<Duration>>,
+    close_codes: BTreeMap<u16, usize>,
+    recovery_requests: usize,
+    recovery_latencies: Vec<Duration>,
+    continued: usize,
+    out_of_order: usize,
+    attempts: BTreeMap<usize, usize>,
+    sse: SseStats,
+}
+
+impl Recorder {
+    /// Records a request that received an HTTP response. Only successful responses count towards
+    /// the latency distribution.
+    pub(crate) fn record_status(&mut self, status: StatusCode, latency: Duration) {
+        self.requests += 1;
+        *self.statuses.entry(status.as_u16()).or_default() += 1;
+        if is_success(status) {
+            self.successes += 1;
+            self.latencies.push(latency);
+        }
+    }
+
+    /// Records a response that counts as successful whatever its status, for endpoints that are
+    /// expected to return errors.
+    pub(crate) fn record_accepted_status(&mut self, status: StatusCode, latency: Duration) {
+        self.requests += 1;
+        *self.statuses.entry(status.as_u16()).or_default() += 1;
+        self.successes += 1;
+        self.latencies.push(latency);
+    }
+
+    /// Records the latency that a request skipped during a stall would have had, to correct for
+    /// coordinated omission. It counts towards the latency distribution but not as a request.
+    pub(crate) fn record_synthesized(&mut self, latency: Duration) {
+        self.synthesized += 1;
+        self.latencies.push(latency);
+    }
+
+    /// Records a request that failed before receiving an HTTP response, such as a connection
+    /// failure or reset.
+    pub(crate) fn record_connection_error(&mut self) {
+        self.requests += 1;
+        self.connection_errors += 1;
+    }
+
+    /// Records a message exchanged over a persistent connection.
+    pub(crate) fn record_message(&mut self, latency: Duration) {
+        self.requests += 1;
+        self.successes += 1;
+        self.latencies.push(latency);
+    }
+
+    /// Records a message that failed because its persistent connection was dropped.
+    pub(crate) fn record_dropped_connection(&mut self) {
+        self.record_connection_error();
+        self.dropped_connections += 1;
+    }
+
+    /// Records the phase timings of a request, in addition to its outcome.
+    pub(crate) fn record_phases(&mut self, phases: &Phases) {
+        for (phase, duration) in phases.iter() {
+            self.phases.entry(phase).or_default().push(duration);
+        }
+        if phases.continued == Some(true) {
+            self.continued += 1;
+        }
+    }
+
+    /// Records the duration of a single named phase of a request, such as a WebSocket handshake.
+    pub(crate) fn record_phase(&mut self, phase: &'static str, duration: Duration) {
+        self.phases.entry(phase).or_default().push(duration);
+    }
+
+    /// Records the close code of a WebSocket connection that closed early, in addition to the
+    /// failure it caused.
+    pub(crate) fn record_close(&mut self, code: u16) {
+        *self.close_codes.entry(code).or_default() += 1;
+    }
+
+    /// Tags the request just recorded as made while recovering from a tunnel reconnect, with its
+    /// latency if it was successful.
+    pub(crate) fn record_recovery(&mut self, latency: Option<Duration>) {
+        self.recovery_requests += 1;
+        self.recovery_latencies.extend(latency);
+    }
+
+    /// Records how many attempts a request that can be retried took, including the first one.
+    pub(crate) fn record_attempts(&mut self, attempts: usize) {
+        *self.attempts.entry(attempts).or_default() += 1;
+    }
+
+    /// Records a batch of pipelined requests whose responses came back in another order.
+    pub(crate) fn record_out_of_order(&mut self) {
+        self.out_of_order += 1;
+    }
+
+    /// Records the events received over an SSE stream, whether or not it completed.
+    pub(crate) fn record_sse(&mut self, stream: &SseStats) {
+        self.sse.merge(stream);
+    }
+
+    /// Records a request whose response failed validation.
+    pub(crate) fn record_invalid(&mut self, kind: &'static str) {
+        self.requests += 1;
+        *self.invalid.entry(kind).or_default() += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: Recorder) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        self.requests += other.requests;
+        self.successes += other.successes;
+        self.connection_errors += other.connection_errors;
+        self.dropped_connections += other.dropped_connections;
+        for (kind, count) in other.invalid {
+            *self.invalid.entry(kind).or_default() += count;
+        }
+        self.synthesized += other.synthesized;
+        for (phase, durations) in other.phases {
+            self.phases.entry(phase).or_default().extend(durations);
+        }
+        for (code, count) in other.close_codes {
+            *self.close_codes.entry(code).or_default() += count;
+        }
+        self.recovery_requests += other.recovery_requests;
+        self.recovery_latencies.extend(other.recovery_latencies);
+        self.continued += other.continued;
+        self.out_of_order += other.out_of_order;
+        for (attempts, count) in other.attempts {
+            *self.attempts.entry(attempts).or_default() += count;
+        }
+        self.sse.merge(&other.sse);
+    }
+}
+
+/// Whether a status code represents a successful exchange, including protocol switches.
+pub(crate) fn is_success(status: StatusCode) -> bool {
+    !(status.is_client_error() || status.is_server_error())
+}
+
+/// Statistics collected from a single benchmark stage.
+#[derive(Debug, Clone)]
+pub struct StageStats {
+    pub concurrency: usize,
+    pub elapsed: Duration,
+    /// Number of responses received for each HTTP status code.
+    pub statuses: BTreeMap<u16, usize>,
+    /// Number of requests that failed without an HTTP response.
+    pub connection_errors: usize,
+    /// Number of persistent connections that were dropped mid-run.
+    pub dropped_connections: usize,
+    /// Number of responses that failed validation, for each kind of failure.
+    pub invalid: BTreeMap<&'static str, usize>,
+    /// Number of latencies synthesized for coordinated omission correction.
+    pub synthesized: usize,
+    /// Number of WebSocket connections that closed early, for each close code.
+    pub close_codes: BTreeMap<u16, usize>,
+    /// Stats for each target, when there are several.
+    pub targets: Vec<(String, StageStats)>,
+    /// Stats for each client connection, when there are several.
+    pub connections: Vec<StageStats>,
+    /// Stats for each endpoint, in a mixed workload.
+    pub endpoints: Vec<(Endpoint, StageStats)>,
+    /// Number of requests made while recovering from a tunnel reconnect.
+    pub recovery_requests: usize,
+    /// Number of requests with `Expect: 100-continue` that received a 100 Continue response.
+    pub continued: usize,
+    /// Number of batches of pipelined requests whose responses came back in another order.
+    pub out_of_order: usize,
+    /// Number of requests that can be retried for each number of attempts they took, including
+    /// the first one.
+    pub attempts: BTreeMap<usize, usize>,
+    /// Events received over SSE streams.
+    pub sse: SseStats,
+    phases: BTreeMap<&'static str, Vec<Duration>>,
+    requests: usize,
+    successes: usize,
+    latencies: Vec<Duration>,
+    recovery_latencies: Vec<Duration>,
+    /// Latencies outside of any recovery, only kept apart when there are recovery latencies.
+    steady_latencies: Vec<Duration>,
+}
+
+impl StageStats {
+    pub(crate) fn new(concurrency: usize, elapsed: Duration, recorder: Recorder) -> Self {
+        let Recorder {
+            mut latencies,
+            statuses,
+            requests,
+            successes,
+            connection_errors,
+            dropped_connections,
+            invalid,
+            synthesized,
+            mut phases,
+            close_codes,
+            recovery_requests,
+            mut recovery_latencies,
+            continued,
+            out_of_order,
+            attempts,
+            sse,
+        } = recorder;
+        latencies.sort_unstable();
+        recovery_latencies.sort_unstable();
+        let steady_latencies = if recovery_latencies.is_empty() {
+            Vec::new()
+        } else {
+            // Both are sorted and every recovery latency is also in the full distribution.
+            let mut recovery = recovery_latencies.iter().peekable();
+            latencies
+                .iter()
+                .copied()
+                .filter(|latency| recovery.next_if_eq(&latency).is_none())
+                .collect()
+        };
+        for durations in phases.values_mut() {
+            durations.sort_unstable();
+        }
+        Self {
+            concurrency,
+            elapsed,
+            statuses,
+            connection_errors,
+            dropped_connections,
+            invalid,
+            synthesized,
+            close_codes,
+            targets: Vec::new(),
+            connections: Vec::new(),
+            endpoints: Vec::new(),
+            recovery_requests,
+            continued,
+            out_of_order,
+            attempts,
+            sse,
+            phases,
+            requests,
+            successes,
+            latencies,
+            recovery_latencies,
+            steady_latencies,
+        }
+    }
+
+    /// Number of requests attempted during the stage.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// Number of requests that completed successfully during the stage.
+    pub fn successes(&self) -> usize {
+        self.successes
+    }
+
+    /// Number of requests that failed, either with an error status or without a response.
+    pub fn failures(&self) -> usize {
+        self.requests() - self.successes()
+    }
+
+    /// Fraction of failed requests, from 0 to 1.
+    pub fn error_rate(&self) -> f64 {
+        if self.requests() == 0 {
+            0.0
+        } else {
+            self.failures() as f64 / self.requests() as f64
+        }
+    }
+
+    /// Successful requests completed per second over the whole stage.
+    pub fn rps(&self) -> f64 {
+        self.successes() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Latency at the given percentile (from 0 to 100), using the nearest-rank method.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        percentile_of(&self.latencies, percentile)
+    }
+
+    /// Latency at the given percentile of the requests made outside of any reconnect recovery.
+    pub fn steady_percentile(&self, percentile: f64) -> Duration {
+        if self.recovery_latencies.is_empty() {
+            self.percentile(percentile)
+        } else {
+            percentile_of(&self.steady_latencies, percentile)
+        }
+    }
+
+    /// Latency at the given percentile of the requests made while recovering from a reconnect.
+    pub fn recovery_percentile(&self, percentile: f64) -> Duration {
+        percentile_of(&self.recovery_latencies, percentile)
+    }
+
+    /// Fraction of the allowed retries that were used, from 0 to 1, when each request that can be
+    /// retried is allowed up to `max_retries` retries. `None` if there's no budget to consume.
+    pub fn retry_budget_consumed(&self, max_retries: usize) -> Option<f64> {
+        let requests: usize = self.attempts.values().sum();
+        let retries: usize = self
+            .attempts
+            .iter()
+            .map(|(attempts, count)| attempts.saturating_sub(1) * count)
+            .sum();
+        (requests > 0 && max_retries > 0).then(|| retries as f64 / (requests * max_retries) as f64)
+    }
+
+    /// Names of the connection phases with timings, if a breakdown was recorded.
+    pub fn phases(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.phases.keys().copied()
+    }
+
+    /// Duration of the given connection phase at the given percentile.
+    pub fn phase_percentile(&self, phase: &str, percentile: f64) -> Duration {
+        self.phases
+            .get(phase)
+            .map(|durations| percentile_of(durations, percentile))
+            .unwrap_or_default()
+    }
+}
+
+/// Final results of a benchmark run, returned by `entrypoint` for embedding the benchmark in other
+/// harnesses.
+#[derive(Debug, Clone)]
+pub struct BenchmarkStats {
+    pub requests: usize,
+    pub failures: usize,
+    /// Fraction of failed requests, from 0 to 1.
+    pub error_rate: f64,
+    /// Successful requests completed per second.
+    pub rps: f64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    /// Payload bytes transferred by successful requests.
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Every statistic collected, including the breakdowns per target and per connection.
+    pub stage: StageStats,
+    /// SLA thresholds that the run violated.
+    pub sla_violations: Vec<String>,
+    /// Metrics that regressed from the baseline beyond the tolerance.
+    pub regressions: Vec<String>,
+}
+
+impl BenchmarkStats {
+    pub(crate) fn new(stage: StageStats, bytes: u64) -> Self {
+        Self {
+            requests: stage.requests(),
+            failures: stage.failures(),
+            error_rate: stage.error_rate(),
+            rps: stage.rps(),
+            p50: stage.percentile(50.0),
+            p90: stage.percentile(90.0),
+            p99: stage.percentile(99.0),
+            bytes,
+            elapsed: stage.elapsed,
+            stage,
+            sla_violations: Vec::new(),
+            regressions: Vec::new(),
+        }
+    }
+
+    /// Fails if the run violated an SLA threshold or regressed from the baseline.
+    pub fn check(&self) -> color_eyre::Result<()> {
+        if !self.sla_violations.is_empty() {
+            return Err(eyre!(
+                "{} SLA threshold(s) violated.",
+                self.sla_violations.len()
+            ));
+        }
+        if !self.regressions.is_empty() {
+            return Err(eyre!(
+                "{} metric(s) regressed from baseline.",
+                self.regressions.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Latencies completed within a sliding time window, for evaluating percentiles while running.
+#[derive(Debug)]
+pub(crate) struct SlidingWindow {
+    window: Duration,
+    samples: Mutex<VecDeque<(Instant, Duration)>>,
+}
+
+impl SlidingWindow {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, latency: Duration) {
+        self.samples
+            .lock()
+            .unwrap()
+            .push_back((Instant::now(), latency));
+    }
+
+    /// Latency at the given percentile among the samples in the window, discarding older ones.
+    pub(crate) fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let mut latencies: Vec<Duration> = {
+            let mut samples = self.samples.lock().unwrap();
+            while samples
+                .front()
+                .is_some_and(|(completed, _)| completed.elapsed() > self.window)
+            {
+                samples.pop_front();
+            }
+            samples.iter().map(|(_, latency)| *latency).collect()
+        };
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        Some(percentile_of(&latencies, percentile))
+    }
+}
+
+/// Completed requests bucketed by the second they finished in, for time-series output.
+#[derive(Debug)]
+pub(crate) struct Timeseries {
+    started: Instant,
+    started_at: SystemTime,
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    requests: usize,
+    /// Latencies of the successful requests.
+    latencies: Vec<Duration>,
+    reconnects: usize,
+}
+
+impl Timeseries {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            buckets: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a completed request, with its latency if it was successful.
+    pub(crate) fn record(&self, latency: Option<Duration>) {
+        let second = self.started.elapsed().as_secs() as usize;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() <= second {
+            buckets.resize_with(second + 1, Bucket::default);
+        }
+        let bucket = &mut buckets[second];
+        bucket.requests += 1;
+        bucket.latencies.extend(latency);
+    }
+
+    /// Renders one CSV row per second, with the Unix timestamp at its start, the requests
+    /// completed, the successful requests per second, and the latency percentiles within it.
+    pub(crate) fn to_csv(&self) -> String {
+        let start = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut csv = String::from("timestamp,requests,rps,p50_ms,p99_ms\n");
+        for (second, bucket) in self.buckets.lock().unwrap().iter_mut().enumerate() {
+            bucket.latencies.sort_unstable();
+            let _ = writeln!(
+                csv,
+                "{},{},{},{:.3},{:.3}",
+                start + second as u64,
+                bucket.requests,
+                bucket.latencies.len(),
+                percentile_of(&bucket.latencies, 50.0).as_secs_f64() * 1000.0,
+                percentile_of(&bucket.latencies, 99.0).as_secs_f64() * 1000.0,
+            );
+        }
+        csv
+    }
+}
+
+/// Requests completed since the last snapshot, for periodic summaries while running.
+#[derive(Debug, Default)]
+pub(crate) struct Snapshot {
+    interval: Mutex<Bucket>,
+}
+
+/// Summary of the requests completed during one snapshot interval.
+pub(crate) struct SnapshotStats {
+    pub(crate) requests: usize,
+    pub(crate) successes: usize,
+    pub(crate) reconnects: usize,
+    pub(crate) p99: Duration,
+}
+
+impl Snapshot {
+    /// Records a completed request, with its latency if it was successful.
+    pub(crate) fn record(&self, latency: Option<Duration>) {
+        let mut interval = self.interval.lock().unwrap();
+        interval.requests += 1;
+        interval.latencies.extend(latency);
+    }
+
+    /// Records a persistent connection that was dropped and will be reopened.
+    pub(crate) fn record_reconnect(&self) {
+        self.interval.lock().unwrap().reconnects += 1;
+    }
+
+    /// Summarizes the interval so far, and starts a new one.
+    pub(crate) fn take(&self) -> SnapshotStats {
+        let mut interval = std::mem::take(&mut *self.interval.lock().unwrap());
+        interval.latencies.sort_unstable();
+        SnapshotStats {
+            requests: interval.requests,
+            successes: interval.latencies.len(),
+            reconnects: interval.reconnects,
+            p99: percentile_of(&interval.latencies, 99.0),
+        }
+    }
+}
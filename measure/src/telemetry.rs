@@ -0,0 +1,73 @@
+use color_eyre::eyre::WrapErr;
+use opentelemetry::{global, propagation::Injector, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tracing::{Subscriber, level_filters::LevelFilter, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+/// Exports spans to an OpenTelemetry collector over OTLP/HTTP, flushing them when dropped.
+pub struct Otlp {
+    provider: SdkTracerProvider,
+}
+
+impl Otlp {
+    pub fn new(endpoint: &str) -> color_eyre::Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .wrap_err_with(|| "Unable to create the OTLP exporter.")?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        Ok(Self { provider })
+    }
+
+    /// Layer exporting every span at the debug level or above, whatever the logging level is, so
+    /// that the per-request spans are always included.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer()
+            .with_tracer(self.provider.tracer(env!("CARGO_PKG_NAME")))
+            .with_filter(LevelFilter::DEBUG)
+    }
+}
+
+impl Drop for Otlp {
+    fn drop(&mut self) {
+        if let Err(error) = self.provider.shutdown() {
+            warn!(%error, "Unable to flush the OpenTelemetry spans.");
+        }
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Adds the `traceparent` header of the current span, so that the service's spans join its trace.
+pub(crate) fn inject_trace_context(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
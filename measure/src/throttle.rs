@@ -0,0 +1,125 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::stream;
+
+use crate::stats::InvalidResponse;
+
+/* Client-side bandwidth limits */
+
+/// Size of the chunks that throttled uploads are split into.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Paces a transfer so that it doesn't exceed a rate, by waiting until each chunk is due.
+pub(crate) struct Throttle {
+    bytes_per_second: u64,
+    started: Instant,
+    transferred: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            started: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    /// Accounts for a chunk, waiting until the total transferred so far is within the rate.
+    pub(crate) async fn consume(&mut self, len: usize) {
+        self.transferred += len as u64;
+        let due = self.started
+            + Duration::from_secs_f64(self.transferred as f64 / self.bytes_per_second as f64);
+        tokio::time::sleep_until(due.into()).await;
+    }
+}
+
+/// Wraps a request body in a stream that is uploaded at most at the given rate, recording the
+/// achieved bandwidth once it's fully sent.
+pub(crate) fn throttled_body(
+    data: Bytes,
+    bytes_per_second: u64,
+    bandwidth: Arc<Bandwidth>,
+) -> impl futures::Stream<Item = Result<Bytes, std::io::Error>> {
+    let throttle = Throttle::new(bytes_per_second);
+    stream::unfold((data, throttle), move |(mut data, mut throttle)| {
+        let bandwidth = Arc::clone(&bandwidth);
+        async move {
+            if data.is_empty() {
+                return None;
+            }
+            let chunk = data.split_to(data.len().min(CHUNK_SIZE));
+            throttle.consume(chunk.len()).await;
+            if data.is_empty() {
+                bandwidth.record_upload(throttle.transferred, throttle.started.elapsed());
+            }
+            Some((Ok(chunk), (data, throttle)))
+        }
+    })
+}
+
+/// Reads a response body at most at the given rate, recording the achieved bandwidth. Fails as
+/// soon as the body grows past `max_size`, if set.
+pub(crate) async fn throttled_read(
+    mut response: reqwest::Response,
+    bytes_per_second: u64,
+    max_size: Option<u64>,
+    bandwidth: &Bandwidth,
+) -> color_eyre::Result<Bytes> {
+    let mut throttle = Throttle::new(bytes_per_second);
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.chunk().await? {
+        if max_size.is_some_and(|max_size| (body.len() + chunk.len()) as u64 > max_size) {
+            return Err(InvalidResponse("response too large").into());
+        }
+        throttle.consume(chunk.len()).await;
+        body.extend_from_slice(&chunk);
+    }
+    bandwidth.record_download(throttle.transferred, throttle.started.elapsed());
+    Ok(body.freeze())
+}
+
+/// Bytes and time spent on throttled transfers across all connections.
+#[derive(Debug, Default)]
+pub(crate) struct Bandwidth {
+    upload_bytes: AtomicU64,
+    upload_nanos: AtomicU64,
+    download_bytes: AtomicU64,
+    download_nanos: AtomicU64,
+}
+
+impl Bandwidth {
+    fn record_upload(&self, bytes: u64, elapsed: Duration) {
+        self.upload_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.upload_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn record_download(&self, bytes: u64, elapsed: Duration) {
+        self.download_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.download_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Average achieved upload rate per connection, in bytes per second.
+    pub(crate) fn upload_bps(&self) -> Option<f64> {
+        rate(&self.upload_bytes, &self.upload_nanos)
+    }
+
+    /// Average achieved download rate per connection, in bytes per second.
+    pub(crate) fn download_bps(&self) -> Option<f64> {
+        rate(&self.download_bytes, &self.download_nanos)
+    }
+}
+
+fn rate(bytes: &AtomicU64, nanos: &AtomicU64) -> Option<f64> {
+    let nanos = nanos.load(Ordering::Relaxed);
+    (nanos > 0).then(|| bytes.load(Ordering::Relaxed) as f64 / (nanos as f64 / 1e9))
+}
@@ -0,0 +1,66 @@
+//! Payload contents shared by the service, which generates them, and measure, which verifies them.
+
+use rand::Rng;
+use serde::Serialize;
+
+/// Content to fill payloads with, to control how well they compress.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize)]
+pub enum PayloadPattern {
+    /// Random bytes, which don't compress.
+    Random,
+    /// Only zero bytes, which compress almost entirely.
+    Zeros,
+    /// Random words from a small vocabulary, which compress like plain text.
+    Text,
+    /// Bytes counting from 0 to 255 over and over.
+    Repeating,
+}
+
+/// Vocabulary for `PayloadPattern::Text`.
+const WORDS: &[&[u8]] = &[
+    b"the",
+    b"of",
+    b"and",
+    b"to",
+    b"in",
+    b"tunnel",
+    b"request",
+    b"response",
+    b"data",
+    b"server",
+    b"client",
+    b"connection",
+    b"benchmark",
+    b"latency",
+    b"throughput",
+    b"sandhole",
+];
+
+impl PayloadPattern {
+    /// Fills the buffer with the pattern, drawing from the RNG for the random patterns. Filling a
+    /// shorter buffer from the same RNG state yields a prefix of the longer one.
+    pub fn fill(self, rng: &mut impl Rng, data: &mut [u8]) {
+        match self {
+            PayloadPattern::Random => rng.fill_bytes(data),
+            PayloadPattern::Zeros => data.fill(0),
+            PayloadPattern::Text => {
+                let mut filled = 0;
+                while filled < data.len() {
+                    let word = WORDS[rng.random_range(0..WORDS.len())];
+                    for &byte in word.iter().chain(b" ") {
+                        let Some(slot) = data.get_mut(filled) else {
+                            break;
+                        };
+                        *slot = byte;
+                        filled += 1;
+                    }
+                }
+            }
+            PayloadPattern::Repeating => {
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte = i as u8;
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,14 @@
 use std::{
-    sync::{Arc, atomic::AtomicU16},
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
     time::Duration,
 };
 
@@ -7,90 +16,873 @@ use axum::{
     Router,
     body::Bytes,
     extract::DefaultBodyLimit,
+    middleware,
+    response::IntoResponse,
     routing::{RouterIntoService, get, post},
 };
 use backon::{ExponentialBuilder, Retryable};
 use color_eyre::eyre::WrapErr;
-use hyper::body::Incoming;
-use hyper_util::service::TowerToHyperService;
-use rand::RngCore;
-use russh::{cipher::Name, client, keys::PrivateKey};
-use tracing::{debug, error, info};
+use futures::FutureExt;
+use hyper::{
+    StatusCode,
+    body::Incoming,
+    header::{CONNECTION, HeaderValue},
+    service::Service,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use russh::{cipher, client, compression, kex, keys::PrivateKey, mac};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{Notify, Semaphore},
+    time::Instant,
+};
+#[cfg(feature = "otel")]
+use tracing::Instrument;
+use tracing::{debug, error, info, warn};
 
 mod routes;
 mod ssh;
+mod stats;
+#[cfg(feature = "otel")]
+mod telemetry;
+
+pub use sandhole_benchmark_payload::PayloadPattern;
 
+#[cfg(feature = "otel")]
+pub use crate::telemetry::Otlp;
 use crate::{
-    routes::{get_handler, post_handler, ws_handler},
-    ssh::TcpForwardSession,
+    routes::{
+        ConcurrencyLimit, GetData, KillConnection, KillTunnel, LatencyData, Limits, access_log,
+        bytes_handler, deflate_handler, flaky_handler, get_handler, gzip_handler, health_handler,
+        kill_handler, latency_handler, limit_concurrency, limits_handler, ping_handler,
+        post_handler, post_info_handler, sink_handler, sse_handler, track_active, trailers_handler,
+        upload_handler, ws_handler,
+    },
+    ssh::{SshConnectOptions, TcpForwardSession, forwarding_hostnames},
+    stats::{ActiveGauge, ReconnectStats, TunnelCounters},
 };
 
 /* Router definitions */
 
-type RouterService = TowerToHyperService<RouterIntoService<Incoming>>;
+/// Hyper service for the Axum application, which abruptly closes the connection instead of
+/// responding when a response is marked with `KillConnection`.
+#[derive(Clone)]
+pub struct RouterService {
+    service: TowerToHyperService<RouterIntoService<Incoming>>,
+    active: Arc<ActiveGauge>,
+    /// SSH session serving the responses, sent as `X-Tunnel-Session` so that clients can tell when
+    /// the tunnel reconnected. Unset when serving directly.
+    session: Option<u64>,
+    /// Index of the tunnel serving the responses, when running several.
+    tunnel: usize,
+    tunnels: Arc<TunnelCounters>,
+    /// Connection serving the responses, sent as `X-Conn-Id` so that clients can tell which
+    /// forwarded channel carried each request.
+    connection: Option<u64>,
+    next_connection: Arc<AtomicU64>,
+    /// When the connection's timeout expires, after which its pending responses are replaced with
+    /// a 503, right before the connection is closed.
+    deadline: Option<Instant>,
+    /// Drops the SSH session serving the responses, for `/kill`. Unset when serving directly.
+    kill: Option<Arc<Notify>>,
+}
+
+impl RouterService {
+    /// Periodically logs how many requests and WebSocket connections are being handled at once.
+    pub async fn log_active(self, interval: Duration) {
+        self.active.log_every(interval).await
+    }
+
+    /// Logs the number of requests served through each tunnel so far on every interval.
+    pub async fn log_tunnels(self, interval: Duration) {
+        self.tunnels.log_every(interval).await
+    }
+
+    /// Logs the total number of requests served through each tunnel.
+    pub fn log_tunnel_summary(&self) {
+        self.tunnels.log_summary();
+    }
+
+    /// Service for the tunnel with the given index, counting its requests apart from the others'.
+    pub fn for_tunnel(&self, tunnel: usize) -> Self {
+        Self {
+            tunnel,
+            ..self.clone()
+        }
+    }
+
+    /// Service for an SSH session, which `/kill` can drop by notifying `kill`.
+    fn for_session(&self, session: u64, kill: Option<Arc<Notify>>) -> Self {
+        Self {
+            session: Some(session),
+            kill,
+            ..self.clone()
+        }
+    }
+
+    /// Service for a new connection, with an ID of its own, and the deadline of its timeout if any.
+    fn for_connection(&self, deadline: Option<Instant>) -> Self {
+        Self {
+            connection: Some(self.next_connection.fetch_add(1, Ordering::Relaxed) + 1),
+            deadline,
+            ..self.clone()
+        }
+    }
+}
+
+/// Error that makes hyper drop a connection killed by the `/kill` endpoint.
+#[derive(Debug)]
+pub(crate) struct ConnectionKilled;
+
+impl std::fmt::Display for ConnectionKilled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("connection killed on request")
+    }
+}
+
+impl std::error::Error for ConnectionKilled {}
+
+impl ConnectionKilled {
+    /// Whether a connection error was caused by the `/kill` endpoint.
+    pub(crate) fn caused(error: &(dyn std::error::Error + 'static)) -> bool {
+        std::iter::successors(Some(error), |error| error.source()).any(|error| error.is::<Self>())
+    }
+}
+
+impl Service<hyper::Request<Incoming>> for RouterService {
+    type Response = hyper::Response<axum::body::Body>;
+    type Error = ConnectionKilled;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, mut request: hyper::Request<Incoming>) -> Self::Future {
+        if let Some(kill) = &self.kill {
+            request
+                .extensions_mut()
+                .insert(KillTunnel(Arc::clone(kill)));
+        }
+        // Echoed back, so that clients can match responses to requests.
+        let request_id = request.headers().get("x-request-id").cloned();
+        #[cfg(feature = "otel")]
+        let span = telemetry::request_span(&request);
+        #[cfg(feature = "otel")]
+        let _entered = span.enter();
+        let response = self.service.call(request);
+        let session = self.session;
+        let connection = self.connection;
+        let deadline = self.deadline;
+        if session.is_some() {
+            self.tunnels.record(self.tunnel);
+        }
+        let future = async move {
+            let response = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, response).await.ok(),
+                None => Some(response.await),
+            };
+            let mut response = match response {
+                Some(response) => response.unwrap_or_else(|never: Infallible| match never {}),
+                None => {
+                    debug!("Connection timed out before the response was ready.");
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        [(CONNECTION, "close")],
+                        "Connection timed out.",
+                    )
+                        .into_response()
+                }
+            };
+            if let Some(session) = session {
+                response
+                    .headers_mut()
+                    .insert("x-tunnel-session", HeaderValue::from(session));
+            }
+            if let Some(connection) = connection {
+                response
+                    .headers_mut()
+                    .insert("x-conn-id", HeaderValue::from(connection));
+            }
+            if let Some(request_id) = request_id {
+                response.headers_mut().insert("x-request-id", request_id);
+            }
+            if response.extensions().get::<KillConnection>().is_some() {
+                Err(ConnectionKilled)
+            } else {
+                Ok(response)
+            }
+        };
+        #[cfg(feature = "otel")]
+        let future = future.instrument(span.clone());
+        Box::pin(future)
+    }
+}
+
+/// Distribution of the delays added by `/latency`, in milliseconds. Parsed from
+/// `uniform:MIN:MAX`, `normal:MEAN:STD_DEV` or `pareto:SCALE:SHAPE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyDistribution {
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+    /// Negative samples are clamped to zero.
+    Normal {
+        mean: f64,
+        std_dev: f64,
+    },
+    /// Heavy-tailed, never below `scale`.
+    Pareto {
+        scale: f64,
+        shape: f64,
+    },
+}
+
+impl FromStr for LatencyDistribution {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(':');
+        let (Some(kind), Some(first), Some(second), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected distribution:param:param".to_string());
+        };
+        let parse = |param: &str| {
+            param
+                .parse::<f64>()
+                .ok()
+                .filter(|param| param.is_finite() && *param >= 0.0)
+                .ok_or_else(|| format!("invalid parameter {param}"))
+        };
+        let (first, second) = (parse(first)?, parse(second)?);
+        match kind {
+            "uniform" if first <= second => Ok(LatencyDistribution::Uniform {
+                min: first,
+                max: second,
+            }),
+            "uniform" => Err("minimum must not exceed maximum".to_string()),
+            "normal" => Ok(LatencyDistribution::Normal {
+                mean: first,
+                std_dev: second,
+            }),
+            "pareto" if second > 0.0 => Ok(LatencyDistribution::Pareto {
+                scale: first,
+                shape: second,
+            }),
+            "pareto" => Err("shape must be positive".to_string()),
+            kind => Err(format!("unknown distribution {kind}")),
+        }
+    }
+}
+
+impl LatencyDistribution {
+    /// Draws a delay from the distribution.
+    pub(crate) fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let millis = match *self {
+            LatencyDistribution::Uniform { min, max } => rng.random_range(min..=max),
+            LatencyDistribution::Normal { mean, std_dev } => {
+                // Box-Muller transform, keeping the first sample in (0, 1] so that its logarithm
+                // is finite.
+                let u = 1.0 - rng.random::<f64>();
+                let v = rng.random::<f64>();
+                mean + std_dev * (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+            }
+            LatencyDistribution::Pareto { scale, shape } => {
+                scale * (1.0 - rng.random::<f64>()).powf(-1.0 / shape)
+            }
+        };
+        Duration::try_from_secs_f64(millis.max(0.0) / 1000.0).unwrap_or(Duration::MAX)
+    }
+}
+
+/// What the router serves, and how.
+#[derive(Clone)]
+pub struct RouterConfig {
+    /// Largest GET response, in bytes.
+    pub max_data_size: usize,
+    /// Largest POST body, in bytes.
+    pub max_post_size: usize,
+    /// Each GET response is offset by up to this many bytes into the data.
+    pub get_jitter_bytes: usize,
+    pub sse_interval: Duration,
+    /// Seed for the GET data, its offsets, the injected errors and the added delays, so that
+    /// clients can verify them and runs can be reproduced.
+    pub seed: Option<u64>,
+    pub payload_pattern: PayloadPattern,
+    /// Delays added by `/latency`, unless the request picks another distribution.
+    pub latency: LatencyDistribution,
+    /// Whether to log every handled request at the info level, instead of the debug level.
+    pub access_log: bool,
+    /// Whether to serve `/flaky`.
+    pub enable_flaky: bool,
+    /// Whether to serve `/kill`.
+    pub enable_kill: bool,
+    /// Number of tunnels serving the router, to count requests for each of them.
+    pub tunnels: usize,
+    /// Requests handled at once, beyond which they're queued.
+    pub max_concurrent_requests: Option<usize>,
+    /// Whether to reject the requests over `max_concurrent_requests`, instead of queueing them.
+    pub reject_over_limit: bool,
+}
 
 /// A lazily-created Router, to be used by the SSH client tunnels.
-pub fn get_router(max_data_size: usize) -> RouterService {
-    let mut data = vec![0u8; max_data_size + usize::from(u16::MAX)];
-    rand::rng().fill_bytes(&mut data);
-    TowerToHyperService::new(
+pub fn get_router(
+    RouterConfig {
+        max_data_size,
+        max_post_size,
+        get_jitter_bytes,
+        sse_interval,
+        seed,
+        payload_pattern,
+        latency,
+        access_log: access_log_enabled,
+        enable_flaky,
+        enable_kill,
+        tunnels,
+        max_concurrent_requests,
+        reject_over_limit,
+    }: RouterConfig,
+) -> RouterService {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    };
+    let mut data = vec![0u8; max_data_size + get_jitter_bytes];
+    payload_pattern.fill(&mut rng, &mut data);
+    let get_data = GetData {
+        data: Bytes::from_static(data.leak()),
+        max_data_size,
+        jitter: get_jitter_bytes,
+        rng: Arc::new(Mutex::new(rng)),
+    };
+    let router = if enable_flaky {
+        Router::new().route(
+            "/flaky/{file_size}",
+            get(flaky_handler).with_state(get_data.clone()),
+        )
+    } else {
         Router::new()
+    };
+    let active = Arc::new(ActiveGauge::default());
+    let router = if enable_kill {
+        router.route("/kill", get(kill_handler))
+    } else {
+        router
+    };
+    let latency_data = LatencyData {
+        get_data: get_data.clone(),
+        distribution: latency,
+    };
+    let service = TowerToHyperService::new(
+        router
+            .route(
+                "/latency/{file_size}",
+                get(latency_handler).with_state(latency_data),
+            )
             .route("/get/{file_size}", get(get_handler))
-            .with_state((Bytes::from_static(data.leak()), Arc::new(AtomicU16::new(0))))
+            .route("/gzip/{file_size}", get(gzip_handler))
+            .route("/deflate/{file_size}", get(deflate_handler))
+            .route("/trailers/{file_size}", get(trailers_handler))
+            .with_state(get_data)
+            .route(
+                "/bytes/{size}",
+                get(bytes_handler).with_state(max_data_size),
+            )
+            .route(
+                "/limits",
+                get(limits_handler).with_state(Limits {
+                    max_data_size,
+                    max_post_size,
+                }),
+            )
+            .route("/health", get(health_handler))
+            .route("/ping", get(ping_handler))
             .route(
                 "/post/{file_size}",
-                post(post_handler).layer(DefaultBodyLimit::max(max_data_size)),
+                post(post_handler).with_state(max_post_size),
+            )
+            .route("/sink/{size}", post(sink_handler).with_state(max_post_size))
+            .route(
+                "/post-info/{file_size}",
+                post(post_info_handler)
+                    .with_state(max_post_size)
+                    .layer(DefaultBodyLimit::max(max_post_size)),
             )
-            .route("/ws", get(ws_handler))
+            .route(
+                "/upload/{file_size}",
+                // Leave room for the multipart boundaries and part headers.
+                post(upload_handler)
+                    .with_state(max_post_size)
+                    .layer(DefaultBodyLimit::max(max_post_size + 4096)),
+            )
+            .route("/ws", get(ws_handler).with_state(Arc::clone(&active)))
+            .route("/sse", get(sse_handler).with_state(sse_interval))
+            .layer(middleware::from_fn_with_state(
+                Arc::clone(&active),
+                track_active,
+            ))
+            // Outside of the active tracking, so that queued requests aren't counted as active.
+            .layer(middleware::from_fn_with_state(
+                max_concurrent_requests.map(|max| ConcurrencyLimit {
+                    semaphore: Arc::new(Semaphore::new(max)),
+                    reject: reject_over_limit,
+                }),
+                limit_concurrency,
+            ))
+            .layer(middleware::from_fn_with_state(
+                access_log_enabled,
+                access_log,
+            ))
             .into_service(),
-    )
+    );
+    RouterService {
+        service,
+        active,
+        session: None,
+        tunnel: 0,
+        tunnels: Arc::new(TunnelCounters::new(tunnels)),
+        connection: None,
+        next_connection: Arc::new(AtomicU64::new(0)),
+        deadline: None,
+        kill: None,
+    }
 }
 
-/// Begins remote port forwarding (reverse tunneling) with Russh to serve an Axum application.
-pub async fn ssh_entrypoint(
-    host: &str,
-    port: u16,
-    login_name: &str,
-    key: Arc<PrivateKey>,
-    ciphers: Vec<Name>,
+/// Serves the Axum application over plain HTTP on a local address, bypassing the tunnel. Useful
+/// as a baseline to measure the tunnel's overhead.
+pub async fn serve_direct(
+    addr: SocketAddr,
     service: RouterService,
-    exec: Option<&str>,
+    http: HttpConfig,
 ) -> color_eyre::Result<()> {
-    let config = Arc::new(client::Config {
-        preferred: russh::Preferred {
-            cipher: std::borrow::Cow::Owned(ciphers),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    let listener = TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("Unable to listen on {addr}."))?;
+    info!(%addr, "Serving directly.");
     loop {
-        let connect = async || {
-            TcpForwardSession::connect_key(
-                host,
-                port,
-                login_name,
-                Arc::clone(&key),
-                Arc::clone(&config),
-                service.clone(),
-            )
-            .await
+        let (socket, peer) = listener.accept().await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            match http.serve(socket, service).await {
+                Ok(()) => {}
+                Err(ConnectionError::Failed(error)) if ConnectionKilled::caused(&*error) => {
+                    debug!(%peer, "Killed direct connection.");
+                }
+                Err(error) => debug!(?error, %peer, "Failed to serve direct connection."),
+            }
+        });
+    }
+}
+
+/// Serves the Axum application over plain HTTP on a Unix socket, bypassing both TCP and the
+/// tunnel. Useful as a baseline of the application's own throughput.
+#[cfg(unix)]
+pub async fn serve_uds(
+    path: PathBuf,
+    service: RouterService,
+    http: HttpConfig,
+) -> color_eyre::Result<()> {
+    let listener = UnixListener::bind(&path)
+        .wrap_err_with(|| format!("Unable to listen on {}.", path.display()))?;
+    info!(path = %path.display(), "Serving over a Unix socket.");
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            match http.serve(socket, service).await {
+                Ok(()) => {}
+                Err(ConnectionError::Failed(error)) if ConnectionKilled::caused(&*error) => {
+                    debug!("Killed Unix socket connection.");
+                }
+                Err(error) => debug!(?error, "Failed to serve Unix socket connection."),
+            }
+        });
+    }
+}
+
+/// Connection settings for the HTTP server, for both tunneled and direct connections.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpConfig {
+    /// Whether HTTP/1.1 connections are kept alive between requests.
+    pub keep_alive: bool,
+    /// How long to wait for a request's headers, or hyper's default if `None`.
+    pub header_read_timeout: Option<Duration>,
+    /// Interval between HTTP/2 keep-alive pings, or no pings if `None`.
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// How long to wait for an HTTP/2 ping to be acknowledged, or hyper's default if `None`.
+    pub http2_keep_alive_timeout: Option<Duration>,
+    /// How long a single connection may stay open before it's closed, or unlimited if `None`.
+    pub connection_timeout: Option<Duration>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            header_read_timeout: None,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: None,
+            connection_timeout: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn builder(self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+        let mut http1 = builder.http1();
+        http1.timer(TokioTimer::new()).keep_alive(self.keep_alive);
+        if let Some(timeout) = self.header_read_timeout {
+            http1.header_read_timeout(timeout);
+        }
+        let mut http2 = builder.http2();
+        http2
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.http2_keep_alive_interval);
+        if let Some(timeout) = self.http2_keep_alive_timeout {
+            http2.keep_alive_timeout(timeout);
+        }
+        builder
+    }
+
+    /// Serves a single connection in isolation from the others: a panic while serving it only
+    /// closes this connection, and so does staying open past `connection_timeout`. Requests still
+    /// pending by then get a 503, with a short grace period to send it. Its responses carry the
+    /// connection's ID.
+    async fn serve<I>(self, io: I, service: RouterService) -> Result<(), ConnectionError>
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let builder = self.builder();
+        let service = service.for_connection(
+            self.connection_timeout
+                .map(|timeout| Instant::now() + timeout),
+        );
+        let connection =
+            AssertUnwindSafe(builder.serve_connection_with_upgrades(TokioIo::new(io), service))
+                .catch_unwind();
+        let served = match self.connection_timeout {
+            Some(timeout) => tokio::time::timeout(timeout + CONNECTION_TIMEOUT_GRACE, connection)
+                .await
+                .map_err(|_| ConnectionError::TimedOut)?,
+            None => connection.await,
         };
-        let mut session = connect
-            .retry(
-                ExponentialBuilder::default()
-                    .with_jitter()
-                    .with_max_delay(Duration::from_secs(20)),
-            )
-            .await
-            .wrap_err_with(|| "SSH connection failed.")?;
-        match session.start_forwarding(exec).await {
-            Err(e) => error!(error = ?e, "TCP forward session failed."),
-            _ => info!("Connection closed."),
+        match served {
+            Ok(result) => result.map_err(ConnectionError::Failed),
+            Err(_) => Err(ConnectionError::Panicked),
         }
-        debug!("Attempting graceful disconnect.");
-        if let Err(e) = session.close().await {
-            debug!(error = ?e, "Graceful disconnect failed.")
+    }
+}
+
+/// Time left after a connection's timeout to send the 503 responses of its pending requests.
+const CONNECTION_TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Why serving a connection stopped with an error.
+#[derive(Debug)]
+pub(crate) enum ConnectionError {
+    /// Hyper failed to serve the connection, or `/kill` closed it.
+    Failed(Box<dyn std::error::Error + Send + Sync>),
+    /// The connection stayed open for longer than `HttpConfig::connection_timeout`.
+    TimedOut,
+    /// Serving the connection panicked.
+    Panicked,
+}
+
+/// IP address family to connect to the SSH server with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    Any,
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(self, addr: &SocketAddr) -> bool {
+        match self {
+            IpFamily::Any => true,
+            IpFamily::V4 => addr.is_ipv4(),
+            IpFamily::V6 => addr.is_ipv6(),
         }
-        debug!("Restarting connection.");
     }
 }
+
+impl std::fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            IpFamily::Any => "IP",
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct SshEntrypointConfig {
+    pub host: String,
+    pub port: u16,
+    pub ip_family: IpFamily,
+    /// Local IP address to bind the SSH connection to, to pick the network interface.
+    pub local_address: Option<IpAddr>,
+    pub login_name: String,
+    pub key: Arc<PrivateKey>,
+    /// Responses to the keyboard-interactive prompts, in order, for servers that require it after
+    /// the key. Such servers are rejected if empty.
+    pub keyboard_interactive: Vec<String>,
+    pub ciphers: Vec<cipher::Name>,
+    /// Key exchange algorithms, or Russh's defaults if empty.
+    pub kex: Vec<kex::Name>,
+    /// MAC algorithms, or Russh's defaults if empty.
+    pub macs: Vec<mac::Name>,
+    /// Whether to prefer zlib compression over no compression.
+    pub compression: bool,
+    pub exec: Option<String>,
+    /// Hostname to request for the remote forwarding.
+    pub bind_hostname: String,
+    pub reconnect: ReconnectConfig,
+    /// Whether to stop after the first session ends, instead of reconnecting.
+    pub once: bool,
+    pub http: HttpConfig,
+    /// Whether the `/kill` endpoint can drop the SSH session that it's requested through, which
+    /// then reconnects.
+    pub enable_kill: bool,
+    /// Connections to open back through the tunnel once the forwarding is assigned.
+    pub prime: PrimeConfig,
+    /// Whether to print the public URL that the server announces on its own line, for scripts.
+    pub print_public_url: bool,
+    /// Number of forwardings to request over the session, to share one SSH connection between
+    /// several tunnels. With more than one, each gets its own hostname derived from
+    /// `bind_hostname`, and its requests are counted under its own tunnel index.
+    pub forwardings: usize,
+}
+
+/// Connections to open back through the tunnel once the forwarding is assigned, by requesting
+/// `/health` from the public URL, to warm up the path before the benchmark starts.
+#[derive(Debug, Clone, Default)]
+pub struct PrimeConfig {
+    pub connections: usize,
+    /// PEM root certificate to trust for the public URL, for proxies with a custom CA.
+    pub ca_cert: Option<PathBuf>,
+    /// Address to connect to instead of resolving the public URL's hostname.
+    pub address: Option<SocketAddr>,
+}
+
+/// Backoff settings for retrying the SSH connection.
+#[derive(Clone)]
+pub struct ReconnectConfig {
+    pub max_delay: Duration,
+    /// Attempts before giving up, or `None` to retry forever.
+    pub max_times: Option<usize>,
+    pub jitter: bool,
+}
+
+impl ReconnectConfig {
+    fn backoff(&self) -> ExponentialBuilder {
+        let builder = ExponentialBuilder::default().with_max_delay(self.max_delay);
+        let builder = match self.max_times {
+            Some(max_times) => builder.with_max_times(max_times),
+            None => builder.without_max_times(),
+        };
+        if self.jitter {
+            builder.with_jitter()
+        } else {
+            builder
+        }
+    }
+}
+
+/// Builds the Russh client configuration from the algorithm preferences.
+fn client_config(
+    ciphers: Vec<cipher::Name>,
+    kex: Vec<kex::Name>,
+    macs: Vec<mac::Name>,
+    compression: bool,
+) -> Arc<client::Config> {
+    let mut preferred = russh::Preferred {
+        cipher: std::borrow::Cow::Owned(ciphers),
+        ..Default::default()
+    };
+    if !kex.is_empty() {
+        preferred.kex = std::borrow::Cow::Owned(kex);
+    }
+    if !macs.is_empty() {
+        preferred.mac = std::borrow::Cow::Owned(macs);
+    }
+    if compression {
+        preferred.compression = std::borrow::Cow::Borrowed(&[
+            compression::ZLIB,
+            compression::ZLIB_LEGACY,
+            compression::NONE,
+        ]);
+    }
+    // Russh doesn't expose the negotiated algorithms, so only the preferences can be logged.
+    info!(
+        ciphers = ?preferred.cipher,
+        kex = ?preferred.kex,
+        macs = ?preferred.mac,
+        compression = ?preferred.compression,
+        "Using SSH algorithm preferences."
+    );
+    Arc::new(client::Config {
+        preferred,
+        ..Default::default()
+    })
+}
+
+/// Connects and authenticates with the SSH server without requesting forwarding, then disconnects.
+pub async fn ssh_check(
+    SshEntrypointConfig {
+        host,
+        port,
+        ip_family,
+        local_address,
+        login_name,
+        key,
+        keyboard_interactive,
+        ciphers,
+        kex,
+        macs,
+        compression,
+        ..
+    }: SshEntrypointConfig,
+) -> color_eyre::Result<()> {
+    let config = client_config(ciphers, kex, macs, compression);
+    let options = SshConnectOptions {
+        host: &host,
+        port,
+        ip_family,
+        local_address,
+        login_name: &login_name,
+        keyboard_interactive: &keyboard_interactive,
+    };
+    let mut session = TcpForwardSession::connect_key(
+        &options,
+        key,
+        config,
+        None,
+        Arc::from([]),
+        HttpConfig::default(),
+        Arc::new(AtomicUsize::new(0)),
+    )
+    .await
+    .wrap_err_with(|| "SSH check failed.")?;
+    info!(%host, port, %login_name, "SSH check succeeded.");
+    session
+        .close()
+        .await
+        .wrap_err_with(|| "Graceful disconnect failed.")?;
+    Ok(())
+}
+
+/// Begins remote port forwarding (reverse tunneling) with Russh to serve an Axum application.
+///
+/// Reconnects indefinitely, unless `once` is set; in that case, the remote exit status of the
+/// single session is returned.
+pub async fn ssh_entrypoint(
+    SshEntrypointConfig {
+        host,
+        port,
+        ip_family,
+        local_address,
+        login_name,
+        key,
+        keyboard_interactive,
+        ciphers,
+        kex,
+        macs,
+        compression,
+        exec,
+        bind_hostname,
+        reconnect,
+        once,
+        http,
+        enable_kill,
+        prime,
+        print_public_url,
+        forwardings,
+    }: SshEntrypointConfig,
+    service: RouterService,
+) -> color_eyre::Result<u32> {
+    let config = client_config(ciphers, kex, macs, compression);
+    let options = SshConnectOptions {
+        host: &host,
+        port,
+        ip_family,
+        local_address,
+        login_name: &login_name,
+        keyboard_interactive: &keyboard_interactive,
+    };
+    let hostnames: Arc<[String]> = forwarding_hostnames(&bind_hostname, forwardings).into();
+    let failed_connections = Arc::new(AtomicUsize::new(0));
+    let mut stats = ReconnectStats::new();
+    let mut session_id = 0;
+    let result = tokio::select! {
+        result = async {
+            loop {
+                session_id += 1;
+                // Each session gets its own, so that `/kill` only drops the one it came through.
+                let kill = enable_kill.then(|| Arc::new(Notify::new()));
+                let session_service = service.for_session(session_id, kill.clone());
+                let connect = async || {
+                    TcpForwardSession::connect_key(
+                        &options,
+                        Arc::clone(&key),
+                        Arc::clone(&config),
+                        Some(session_service.clone()),
+                        Arc::clone(&hostnames),
+                        http,
+                        Arc::clone(&failed_connections),
+                    )
+                    .await
+                };
+                let connecting = Instant::now();
+                let mut session = connect
+                    .retry(reconnect.backoff())
+                    .await
+                    .wrap_err_with(|| "SSH connection failed.")?;
+                // Includes any failed attempts, but is otherwise the setup cost of the session.
+                info!(elapsed = ?connecting.elapsed(), "SSH session authenticated.");
+                stats.connected();
+                let forwarding = session.start_forwarding(
+                    exec.as_deref(),
+                    &hostnames,
+                    &prime,
+                    print_public_url,
+                );
+                // A killed session is dropped and reconnected, even with `once`.
+                let result = match &kill {
+                    Some(kill) => tokio::select! {
+                        result = forwarding => Some(result),
+                        _ = kill.notified() => None,
+                    },
+                    None => Some(forwarding.await),
+                };
+                stats.disconnected();
+                match &result {
+                    Some(Err(e)) => error!(error = ?e, "TCP forward session failed."),
+                    Some(Ok(_)) => info!("Connection closed."),
+                    None => warn!("Session killed on request."),
+                }
+                debug!("Attempting graceful disconnect.");
+                if let Err(e) = session.close().await {
+                    debug!(error = ?e, "Graceful disconnect failed.")
+                }
+                if once && let Some(result) = result {
+                    return result;
+                }
+                debug!("Restarting connection.");
+            }
+        } => result,
+        _ = tokio::signal::ctrl_c() => {
+            info!("Interrupted, shutting down.");
+            Ok(0)
+        }
+    };
+    stats.log_summary();
+    result
+}
@@ -1,12 +1,30 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use clap::Parser;
+use color_eyre::eyre::eyre;
+use futures::future::select_all;
 use russh::{
     cipher::{AES_256_GCM, CHACHA20_POLY1305, Name},
+    kex,
     keys::load_secret_key,
+    mac,
+};
+#[cfg(feature = "otel")]
+use sandhole_benchmark_service::Otlp;
+#[cfg(unix)]
+use sandhole_benchmark_service::serve_uds;
+use sandhole_benchmark_service::{
+    HttpConfig, IpFamily, LatencyDistribution, PayloadPattern, PrimeConfig, ReconnectConfig,
+    RouterConfig, SshEntrypointConfig, get_router, serve_direct, ssh_check, ssh_entrypoint,
 };
-use sandhole_benchmark_service::{get_router, ssh_entrypoint};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::{Instrument, error, info, info_span};
+use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Clone)]
 struct CipherName(Name);
@@ -17,6 +35,12 @@ impl ToString for CipherName {
     }
 }
 
+#[derive(Clone)]
+struct KexName(kex::Name);
+
+#[derive(Clone)]
+struct MacName(mac::Name);
+
 #[derive(clap::Parser)]
 pub struct Config {
     /// SSH hostname.
@@ -26,6 +50,19 @@ pub struct Config {
     #[arg(long, short, default_value_t = 22)]
     port: u16,
 
+    /// Only connect to the SSH server over IPv4.
+    #[arg(long, conflicts_with = "ipv6_only")]
+    ipv4_only: bool,
+
+    /// Only connect to the SSH server over IPv6.
+    #[arg(long)]
+    ipv6_only: bool,
+
+    /// Local IP address to bind the SSH connection to, to send it through a specific network
+    /// interface.
+    #[arg(long)]
+    local_address: Option<IpAddr>,
+
     /// SSH user name.
     #[arg(long, short = 'l', default_value = "sandhole-benchmark")]
     username: String,
@@ -34,17 +71,199 @@ pub struct Config {
     #[arg(long, short = 'i')]
     private_key: PathBuf,
 
+    /// Response to a keyboard-interactive prompt, for servers that require it after accepting the
+    /// key. Repeat for each prompt, in order.
+    #[arg(long)]
+    keyboard_interactive: Vec<String>,
+
     /// Maximum data size to handle for GET requests.
     #[arg(long, short = 'd', default_value_t = 100_000_000)]
     max_data_size: usize,
 
+    /// Maximum body size to accept for POST requests. Defaults to `--max-data-size`.
+    #[arg(long)]
+    max_post_size: Option<usize>,
+
+    /// Upper bound for the random offset of each GET response into the shared buffer, so that
+    /// identical requests don't get identical responses. Verification by measure supports up to
+    /// 65536.
+    #[arg(
+        long,
+        default_value_t = 65536,
+        value_parser = clap::value_parser!(u32).range(..=65536)
+    )]
+    get_jitter_bytes: u32,
+
+    /// Seed for generating the GET data and offsets deterministically.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Content of the GET data, to control how well it compresses. Clients verifying the data must
+    /// use the same pattern.
+    #[arg(long, value_enum, default_value = "random")]
+    payload_pattern: PayloadPattern,
+
+    /// Default distribution of the delays added by `/latency/{size}`, in milliseconds, as
+    /// `uniform:MIN:MAX`, `normal:MEAN:STD_DEV` or `pareto:SCALE:SHAPE`. Requests can pick another
+    /// one with `?distribution=`.
+    #[arg(long, default_value = "uniform:0:100")]
+    latency_distribution: LatencyDistribution,
+
+    /// Interval between events sent by the Server-Sent Events endpoint.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "100ms")]
+    sse_interval: Duration,
+
     /// Ciphers to use with SSH.
     #[arg(long, short, value_parser = validate_cipher, default_values_t = vec![CipherName(CHACHA20_POLY1305), CipherName(AES_256_GCM)])]
     cipher: Vec<CipherName>,
 
+    /// Key exchange algorithms to use with SSH. Defaults to Russh's preferences.
+    #[arg(long, short, value_parser = validate_kex)]
+    kex: Vec<KexName>,
+
+    /// MAC algorithms to use with SSH. Defaults to Russh's preferences.
+    #[arg(long, short, value_parser = validate_mac)]
+    mac: Vec<MacName>,
+
+    /// Prefer zlib compression for the SSH connection.
+    #[arg(long)]
+    ssh_compression: bool,
+
     /// Flags to pass via exec.
     #[arg(long, short)]
     exec: Option<String>,
+
+    /// Hostname or subdomain to request for the remote forwarding.
+    #[arg(long, short, default_value = "measure")]
+    bind_hostname: String,
+
+    /// Maximum delay between SSH reconnection attempts.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "20s")]
+    reconnect_max_delay: Duration,
+
+    /// Reconnection attempts before giving up, or 0 to never give up.
+    #[arg(long, default_value_t = 3)]
+    reconnect_max_times: usize,
+
+    /// Whether to add jitter to the delay between reconnection attempts.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    reconnect_jitter: bool,
+
+    /// Exit after the first session ends instead of reconnecting, with the remote exit status.
+    #[arg(long)]
+    once: bool,
+
+    /// Number of independent SSH connections to forward the same hostname through, to check how
+    /// the proxy balances requests across them. The service stops when any of them does.
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    tunnels: usize,
+
+    /// Request the forwarding of every tunnel over a single SSH connection, instead of one
+    /// connection each, to spare the handshakes and authentications when fanning out. Requires
+    /// `--tunnels` above 1. Each tunnel requests its own hostname, with its index appended to the
+    /// first label of `--bind-hostname` (e.g. `measure-0`), so target all of them to
+    /// spread the load. The setup time of each session is logged, to compare both modes.
+    #[arg(long)]
+    shared_session: bool,
+
+    /// Open this many connections back through the tunnel once the forwarding is assigned, by
+    /// requesting `/health` from the assigned hostname, to warm up the path before the benchmark.
+    #[arg(long, default_value_t = 0)]
+    prime_connections: usize,
+
+    /// PEM root certificate to trust when priming, for proxies with a custom CA.
+    #[arg(long, requires = "prime_connections")]
+    prime_ca_cert: Option<PathBuf>,
+
+    /// Send the priming requests to this address instead of resolving the assigned hostname.
+    #[arg(long, requires = "prime_connections")]
+    prime_address: Option<SocketAddr>,
+
+    /// Print the public URL that the server assigns on its own line of standard output, to grab
+    /// it from scripts. It's also logged as `public_url` either way.
+    #[arg(long)]
+    print_public_url: bool,
+
+    /// Only validate the key, host reachability and authentication, then disconnect.
+    #[arg(long)]
+    check: bool,
+
+    /// Log every handled request at the info level, instead of the debug level.
+    #[arg(long)]
+    access_log: bool,
+
+    /// Keep HTTP/1.1 connections alive between requests.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    http1_keep_alive: bool,
+
+    /// How long to wait for a request's headers before closing the connection. Defaults to
+    /// hyper's timeout.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    header_read_timeout: Option<Duration>,
+
+    /// Interval between HTTP/2 keep-alive pings. Pings are disabled if unset.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    http2_keep_alive_interval: Option<Duration>,
+
+    /// How long to wait for an HTTP/2 keep-alive ping to be acknowledged.
+    #[arg(long, value_parser = humantime::parse_duration, requires = "http2_keep_alive_interval")]
+    http2_keep_alive_timeout: Option<Duration>,
+
+    /// Close connections that stay open for longer than this, so that a slow or hung client can't
+    /// hold on to one forever. Requests still pending by then get a 503. Connections are never
+    /// closed if unset.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    connection_timeout: Option<Duration>,
+
+    /// Handle at most this many requests at once, queueing the rest until a response is sent, to
+    /// model a backend that accepts less load than the proxy forwards. Unlimited if unset.
+    #[arg(long)]
+    max_concurrent_requests: Option<usize>,
+
+    /// Reject the requests over `--max-concurrent-requests` with a 503, instead of queueing them.
+    #[arg(long, requires = "max_concurrent_requests")]
+    reject_over_limit: bool,
+
+    /// Serve `/flaky/{size}?error_rate=`, which fails that fraction of requests with a server
+    /// error.
+    #[arg(long)]
+    enable_flaky: bool,
+
+    /// Serve `/kill`, which abruptly closes the connection it's requested through, and also drops
+    /// the SSH session with `?tunnel=true`.
+    #[arg(long)]
+    enable_kill: bool,
+
+    /// Interval for logging how many requests and WebSocket connections are active at once.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    active_log_interval: Duration,
+
+    /// Also serve over plain HTTP on this local address, to benchmark without the tunnel.
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    /// Also serve over plain HTTP on a Unix socket at this path, to benchmark the service without
+    /// TCP or the tunnel. Only supported on Unix platforms.
+    #[arg(long)]
+    uds: Option<PathBuf>,
+
+    /// Number of Tokio runtime threads. Defaults to the available parallelism.
+    #[arg(long, default_value_t = default_worker_threads())]
+    worker_threads: usize,
+
+    /// OTLP/HTTP endpoint to export traces to, such as `http://localhost:4318/v1/traces`. Requests
+    /// with a `traceparent` header continue the client's trace. Requires building with the `otel`
+    /// feature.
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+}
+
+fn default_worker_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
 }
 
 fn validate_cipher(value: &str) -> Result<CipherName, String> {
@@ -53,30 +272,163 @@ fn validate_cipher(value: &str) -> Result<CipherName, String> {
         .map_err(|_| "invalid domain".to_string())
 }
 
-#[tokio::main]
-async fn main() -> color_eyre::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::builder()
-                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .with(tracing_subscriber::fmt::Layer::default().compact())
-        .init();
-    color_eyre::install()?;
+fn validate_kex(value: &str) -> Result<KexName, String> {
+    kex::Name::try_from(value)
+        .map(KexName)
+        .map_err(|_| "invalid key exchange algorithm".to_string())
+}
+
+fn validate_mac(value: &str) -> Result<MacName, String> {
+    mac::Name::try_from(value)
+        .map(MacName)
+        .map_err(|_| "invalid MAC algorithm".to_string())
+}
+
+fn main() -> color_eyre::Result<()> {
     let config = Config::parse();
-    ssh_entrypoint(
-        &config.host,
-        config.port,
-        &config.username,
-        Arc::new(load_secret_key(config.private_key, None)?),
-        config
+    if config.otlp_endpoint.is_some() && !cfg!(feature = "otel") {
+        return Err(eyre!(
+            "OpenTelemetry export requires building the service with the `otel` feature."
+        ));
+    }
+    #[cfg(feature = "otel")]
+    let otlp = config.otlp_endpoint.as_deref().map(Otlp::new).transpose()?;
+    let registry = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::Layer::default()
+            .compact()
+            .with_filter(
+                tracing_subscriber::EnvFilter::builder()
+                    .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                    .from_env_lossy(),
+            ),
+    );
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otlp.as_ref().map(|otlp| otlp.layer()));
+    registry.init();
+    color_eyre::install()?;
+    if config.uds.is_some() && !cfg!(unix) {
+        return Err(eyre!("Unix sockets are only supported on Unix platforms."));
+    }
+    if config.shared_session && config.tunnels < 2 {
+        return Err(eyre!("A shared session requires more than one tunnel."));
+    }
+    let http = HttpConfig {
+        keep_alive: config.http1_keep_alive,
+        header_read_timeout: config.header_read_timeout,
+        http2_keep_alive_interval: config.http2_keep_alive_interval,
+        http2_keep_alive_timeout: config.http2_keep_alive_timeout,
+        connection_timeout: config.connection_timeout,
+    };
+    let ssh_config = SshEntrypointConfig {
+        host: config.host,
+        port: config.port,
+        ip_family: if config.ipv4_only {
+            IpFamily::V4
+        } else if config.ipv6_only {
+            IpFamily::V6
+        } else {
+            IpFamily::Any
+        },
+        local_address: config.local_address,
+        login_name: config.username,
+        key: Arc::new(load_secret_key(config.private_key, None)?),
+        keyboard_interactive: config.keyboard_interactive,
+        ciphers: config
             .cipher
             .into_iter()
             .map(|cipher_name| cipher_name.0)
             .collect(),
-        get_router(config.max_data_size),
-        config.exec.as_ref().map(String::as_str),
-    )
-    .await
+        kex: config.kex.into_iter().map(|kex_name| kex_name.0).collect(),
+        macs: config.mac.into_iter().map(|mac_name| mac_name.0).collect(),
+        compression: config.ssh_compression,
+        exec: config.exec,
+        bind_hostname: config.bind_hostname,
+        reconnect: ReconnectConfig {
+            max_delay: config.reconnect_max_delay,
+            max_times: (config.reconnect_max_times != 0).then_some(config.reconnect_max_times),
+            jitter: config.reconnect_jitter,
+        },
+        once: config.once,
+        http,
+        enable_kill: config.enable_kill,
+        prime: PrimeConfig {
+            connections: config.prime_connections,
+            ca_cert: config.prime_ca_cert,
+            address: config.prime_address,
+        },
+        forwardings: if config.shared_session {
+            config.tunnels
+        } else {
+            1
+        },
+        print_public_url: config.print_public_url,
+    };
+    info!(worker_threads = config.worker_threads, "Starting runtime.");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .enable_all()
+        .build()?;
+    if config.check {
+        return runtime.block_on(ssh_check(ssh_config));
+    }
+    let service = get_router(RouterConfig {
+        max_data_size: config.max_data_size,
+        max_post_size: config.max_post_size.unwrap_or(config.max_data_size),
+        get_jitter_bytes: config.get_jitter_bytes as usize,
+        sse_interval: config.sse_interval,
+        seed: config.seed,
+        payload_pattern: config.payload_pattern,
+        latency: config.latency_distribution,
+        access_log: config.access_log,
+        enable_flaky: config.enable_flaky,
+        enable_kill: config.enable_kill,
+        tunnels: config.tunnels,
+        max_concurrent_requests: config.max_concurrent_requests,
+        reject_over_limit: config.reject_over_limit,
+    });
+    let exit_status = runtime.block_on(async {
+        tokio::spawn(service.clone().log_active(config.active_log_interval));
+        if let Some(listen) = config.listen {
+            let service = service.clone();
+            tokio::spawn(async move {
+                if let Err(error) = serve_direct(listen, service, http).await {
+                    error!(?error, "Direct server failed.");
+                }
+            });
+        }
+        #[cfg(unix)]
+        if let Some(uds) = config.uds {
+            let service = service.clone();
+            tokio::spawn(async move {
+                if let Err(error) = serve_uds(uds, service, http).await {
+                    error!(?error, "Unix socket server failed.");
+                }
+            });
+        }
+        if config.tunnels == 1 {
+            return ssh_entrypoint(ssh_config, service).await;
+        }
+        tokio::spawn(service.clone().log_tunnels(config.active_log_interval));
+        if config.shared_session {
+            let result = ssh_entrypoint(ssh_config, service.clone()).await;
+            service.log_tunnel_summary();
+            return result;
+        }
+        let tunnels = (0..config.tunnels).map(|tunnel| {
+            Box::pin(
+                ssh_entrypoint(ssh_config.clone(), service.for_tunnel(tunnel))
+                    .instrument(info_span!("tunnel", index = tunnel)),
+            )
+        });
+        let (result, _, _) = select_all(tunnels).await;
+        service.log_tunnel_summary();
+        result
+    })?;
+    if exit_status != 0 {
+        // Exiting skips destructors, so the remaining spans must be flushed first.
+        #[cfg(feature = "otel")]
+        drop(otlp);
+        std::process::exit(exit_status as i32);
+    }
+    Ok(())
 }
@@ -1,46 +1,640 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicU16, Ordering},
+use std::{
+    convert::Infallible,
+    io::Write,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use axum::{
-    body,
-    extract::{Path, State, WebSocketUpgrade},
-    response::IntoResponse,
+    Extension, Json,
+    body::{self, HttpBody},
+    extract::{
+        Multipart, Path, Query, Request, State, WebSocketUpgrade, rejection::BytesRejection,
+    },
+    middleware::Next,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
 use bytes::Bytes;
-use futures::StreamExt;
-use hyper::StatusCode;
+use flate2::{
+    Compression,
+    write::{DeflateEncoder, GzEncoder},
+};
+use futures::{Stream, StreamExt, stream};
+use http_body_util::StreamBody;
+use hyper::{
+    HeaderMap, StatusCode,
+    body::{Frame, SizeHint},
+    header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderValue, TRAILER},
+};
+use rand::{Rng, RngCore, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::{LatencyDistribution, stats::ActiveGauge};
+
+/* Access logging */
+
+/// Logs each request's method, path, status, response size (if known upfront) and handling time,
+/// at the info level if the access log is enabled, or at the debug level otherwise.
+pub(crate) async fn access_log(
+    State(enabled): State<bool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+    let status = response.status().as_u16();
+    let size = response.body().size_hint().exact();
+    if enabled {
+        info!(%method, %path, status, ?size, ?elapsed, "Handled request.");
+    } else {
+        debug!(%method, %path, status, ?size, ?elapsed, "Handled request.");
+    }
+    response
+}
+
+/* Active connection tracking */
+
+/// Response body that holds on to a guard, such as one keeping its request counted as active,
+/// until it's fully sent or dropped.
+struct TrackedBody<G> {
+    body: body::Body,
+    _guard: G,
+}
+
+impl<G: Unpin> HttpBody for TrackedBody<G> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Counts each request as active from when it arrives until its response is sent.
+pub(crate) async fn track_active(
+    State(gauge): State<Arc<ActiveGauge>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let guard = gauge.request();
+    next.run(request).await.map(|body| {
+        body::Body::new(TrackedBody {
+            body,
+            _guard: guard,
+        })
+    })
+}
+
+/* Concurrency limiting */
+
+/// Limit on the requests handled at once, to model a backend that accepts less load than it's
+/// sent.
+#[derive(Clone)]
+pub(crate) struct ConcurrencyLimit {
+    pub(crate) semaphore: Arc<Semaphore>,
+    /// Reject the requests over the limit with a 503, instead of queueing them.
+    pub(crate) reject: bool,
+}
+
+/// Holds one of the limit's permits for each request until its response is sent, so that the
+/// requests beyond the limit wait for a permit or get rejected.
+pub(crate) async fn limit_concurrency(
+    State(limit): State<Option<ConcurrencyLimit>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limit) = limit else {
+        return next.run(request).await;
+    };
+    let permit = if limit.reject {
+        limit.semaphore.try_acquire_owned().ok()
+    } else {
+        limit.semaphore.acquire_owned().await.ok()
+    };
+    let Some(permit) = permit else {
+        debug!("Rejecting a request over the concurrency limit.");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    next.run(request).await.map(|body| {
+        body::Body::new(TrackedBody {
+            body,
+            _guard: permit,
+        })
+    })
+}
 
 /* Endpoints handling */
 
+/// Shared buffer for the GET endpoint, with slack after `max_data_size` for offsetting responses.
+#[derive(Clone)]
+pub(crate) struct GetData {
+    pub(crate) data: Bytes,
+    pub(crate) max_data_size: usize,
+    /// Each response is offset by a random value in `0..jitter`, so that identical requests don't
+    /// get identical responses.
+    pub(crate) jitter: usize,
+    pub(crate) rng: Arc<Mutex<StdRng>>,
+}
+
 pub(crate) async fn get_handler(
     Path(file_size): Path<usize>,
-    State(data): State<(Bytes, Arc<AtomicU16>)>,
+    State(get_data): State<GetData>,
 ) -> impl IntoResponse {
-    if file_size > data.0.len() {
-        StatusCode::BAD_REQUEST.into_response()
+    if file_size > get_data.max_data_size {
+        return size_exceeded(get_data.max_data_size);
+    }
+    let offset = if get_data.jitter == 0 {
+        0
     } else {
-        let pad: usize = data.1.fetch_add(1, Ordering::AcqRel).into();
-        data.0.slice(pad..file_size + pad).into_response()
+        get_data
+            .rng
+            .lock()
+            .unwrap()
+            .random_range(0..get_data.jitter)
+    };
+    // Expose the offset so that clients can verify the data against a seeded buffer. The content
+    // type is set explicitly, so that clients can detect if the tunnel rewrites it.
+    (
+        [
+            (
+                CONTENT_TYPE.as_str(),
+                "application/octet-stream".to_string(),
+            ),
+            ("x-data-offset", offset.to_string()),
+        ],
+        get_data.data.slice(offset..offset + file_size),
+    )
+        .into_response()
+}
+
+/// Query parameters for the flaky endpoint.
+#[derive(Deserialize)]
+pub(crate) struct FlakyParams {
+    /// Fraction of requests to fail, from 0 to 1.
+    error_rate: f64,
+}
+
+/// Server errors returned by the flaky endpoint, to exercise the client's error categorization.
+const FLAKY_STATUSES: [StatusCode; 4] = [
+    StatusCode::INTERNAL_SERVER_ERROR,
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+/// Fails the given fraction of requests with a server error, drawn from the shared RNG so that
+/// seeded runs fail the same requests, and otherwise behaves like the GET endpoint.
+pub(crate) async fn flaky_handler(
+    Path(file_size): Path<usize>,
+    Query(FlakyParams { error_rate }): Query<FlakyParams>,
+    State(get_data): State<GetData>,
+) -> Response {
+    if !(0.0..=1.0).contains(&error_rate) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let failure = {
+        let mut rng = get_data.rng.lock().unwrap();
+        rng.random_bool(error_rate)
+            .then(|| FLAKY_STATUSES[rng.random_range(0..FLAKY_STATUSES.len())])
+    };
+    if let Some(status) = failure {
+        debug!(%status, "Injecting flaky error.");
+        return status.into_response();
+    }
+    get_handler(Path(file_size), State(get_data))
+        .await
+        .into_response()
+}
+
+/// Query parameters for the latency endpoint.
+#[derive(Deserialize)]
+pub(crate) struct LatencyParams {
+    /// Distribution to draw this request's delay from, instead of the service's default.
+    distribution: Option<String>,
+}
+
+/// Shared GET buffer, along with the default distribution for the latency endpoint.
+#[derive(Clone)]
+pub(crate) struct LatencyData {
+    pub(crate) get_data: GetData,
+    pub(crate) distribution: LatencyDistribution,
+}
+
+/// Waits for a delay drawn from the shared RNG, so that seeded runs get the same delays, and
+/// otherwise behaves like the GET endpoint. The delay is sent back in `x-latency-us`, to compare
+/// against the client's measurements.
+pub(crate) async fn latency_handler(
+    Path(file_size): Path<usize>,
+    Query(LatencyParams { distribution }): Query<LatencyParams>,
+    State(LatencyData {
+        get_data,
+        distribution: default,
+    }): State<LatencyData>,
+) -> Response {
+    let distribution = match distribution
+        .as_deref()
+        .map(str::parse::<LatencyDistribution>)
+    {
+        None => default,
+        Some(Ok(distribution)) => distribution,
+        Some(Err(error)) => return (StatusCode::BAD_REQUEST, error).into_response(),
+    };
+    let delay = distribution.sample(&mut *get_data.rng.lock().unwrap());
+    tokio::time::sleep(delay).await;
+    let mut response = get_handler(Path(file_size), State(get_data))
+        .await
+        .into_response();
+    response.headers_mut().insert(
+        "x-latency-us",
+        HeaderValue::from(u64::try_from(delay.as_micros()).unwrap_or(u64::MAX)),
+    );
+    response
+}
+
+/// Size of the chunks that the trailers endpoint streams its body in.
+const TRAILERS_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Streams the start of the GET buffer in chunks, followed by an `x-checksum` trailer with the
+/// body's CRC32, to test that trailers pass through the tunnel. Clients must send `TE: trailers`
+/// for HTTP/1.1 responses to carry them.
+pub(crate) async fn trailers_handler(
+    Path(file_size): Path<usize>,
+    State(get_data): State<GetData>,
+) -> Response {
+    if file_size > get_data.max_data_size {
+        return size_exceeded(get_data.max_data_size);
     }
+    let data = get_data.data.slice(..file_size);
+    let mut trailers = HeaderMap::new();
+    trailers.insert("x-checksum", HeaderValue::from(crc32fast::hash(&data)));
+    let chunks = (0..file_size)
+        .step_by(TRAILERS_CHUNK_SIZE)
+        .map(move |start| {
+            Ok::<_, Infallible>(Frame::data(
+                data.slice(start..(start + TRAILERS_CHUNK_SIZE).min(file_size)),
+            ))
+        })
+        .chain([Ok(Frame::trailers(trailers))]);
+    (
+        [(TRAILER, "x-checksum")],
+        body::Body::new(StreamBody::new(stream::iter(chunks))),
+    )
+        .into_response()
 }
 
+/// Serves the start of the GET buffer gzip-encoded, regardless of `Accept-Encoding`, to test that
+/// pre-encoded bodies pass through the tunnel untouched.
+pub(crate) async fn gzip_handler(
+    Path(file_size): Path<usize>,
+    State(get_data): State<GetData>,
+) -> impl IntoResponse {
+    encoded(file_size, get_data, "gzip").await
+}
+
+/// Serves the start of the GET buffer deflate-encoded, regardless of `Accept-Encoding`.
+pub(crate) async fn deflate_handler(
+    Path(file_size): Path<usize>,
+    State(get_data): State<GetData>,
+) -> impl IntoResponse {
+    encoded(file_size, get_data, "deflate").await
+}
+
+async fn encoded(file_size: usize, get_data: GetData, encoding: &'static str) -> Response {
+    if file_size > get_data.max_data_size {
+        return size_exceeded(get_data.max_data_size);
+    }
+    let data = get_data.data.slice(..file_size);
+    // Compressing large buffers takes a while, so keep it off the async workers.
+    let encoded = tokio::task::spawn_blocking(move || match encoding {
+        "gzip" => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&data)?;
+            encoder.finish()
+        }
+        _ => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&data)?;
+            encoder.finish()
+        }
+    })
+    .await;
+    match encoded {
+        Ok(Ok(encoded)) => ([(CONTENT_ENCODING, encoding)], encoded).into_response(),
+        error => {
+            warn!(?error, encoding, "Unable to encode the GET data.");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Responds right away, for checking that the service is reachable.
+pub(crate) async fn health_handler() -> &'static str {
+    "OK"
+}
+
+/// Responds with a fixed body, for measuring latency without any payload effects.
+pub(crate) async fn ping_handler() -> &'static str {
+    "pong"
+}
+
+/// Limits that the service enforces, so that clients can adapt to them.
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct Limits {
+    pub(crate) max_data_size: usize,
+    pub(crate) max_post_size: usize,
+}
+
+pub(crate) async fn limits_handler(State(limits): State<Limits>) -> Json<Limits> {
+    Json(limits)
+}
+
+/// Returns exactly `size` freshly generated random bytes, without the shared GET buffer.
+pub(crate) async fn bytes_handler(
+    Path(size): Path<usize>,
+    State(max_data_size): State<usize>,
+) -> impl IntoResponse {
+    if size > max_data_size {
+        return size_exceeded(max_data_size);
+    }
+    let mut data = vec![0u8; size];
+    rand::rng().fill_bytes(&mut data);
+    (
+        [(CONTENT_TYPE, "application/octet-stream")],
+        Bytes::from(data),
+    )
+        .into_response()
+}
+
+/// Error returned when a requested size or POST body is larger than the server accepts.
+#[derive(Serialize)]
+pub(crate) struct LimitExceeded {
+    error: &'static str,
+    /// Maximum size in bytes, as configured with `--max-data-size` for GET requests or
+    /// `--max-post-size` for POST bodies.
+    limit: usize,
+}
+
+fn limit_exceeded(max_post_size: usize) -> axum::response::Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(LimitExceeded {
+            error: "body exceeds the configured limit",
+            limit: max_post_size,
+        }),
+    )
+        .into_response()
+}
+
+/// Rejects a GET request for more data than the service's buffer holds, with the limit in the
+/// body so that clients can tell this apart from other bad requests.
+fn size_exceeded(max_data_size: usize) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(LimitExceeded {
+            error: "size exceeds the configured limit",
+            limit: max_data_size,
+        }),
+    )
+        .into_response()
+}
+
+/// Counts the POST body as it streams in, without buffering it, so that large uploads don't need
+/// to fit in memory.
 pub(crate) async fn post_handler(
     Path(file_size): Path<usize>,
-    body: body::Bytes,
+    State(max_post_size): State<usize>,
+    body: body::Body,
+) -> impl IntoResponse {
+    if file_size > max_post_size {
+        warn!(file_size, max_post_size, "POST size exceeds the maximum.");
+        return limit_exceeded(max_post_size);
+    }
+    let mut received = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => {
+                received += chunk.len();
+                // Stop reading as soon as the client sends more than it announced.
+                if received > file_size {
+                    warn!(received, expected = file_size, "POST body is too long.");
+                    return StatusCode::BAD_REQUEST.into_response();
+                }
+            }
+            Err(error) => {
+                warn!(
+                    ?error,
+                    received,
+                    expected = file_size,
+                    "POST body was cut short."
+                );
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        }
+    }
+    if received == file_size {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        warn!(received, expected = file_size, "POST body is too short.");
+        StatusCode::BAD_REQUEST.into_response()
+    }
+}
+
+/// Drains a POST body of up to `size` bytes as it streams in, discarding it, to measure the upload
+/// bandwidth alone. Unlike `/post`, shorter bodies are also accepted.
+pub(crate) async fn sink_handler(
+    Path(size): Path<usize>,
+    State(max_post_size): State<usize>,
+    body: body::Body,
+) -> impl IntoResponse {
+    if size > max_post_size {
+        warn!(size, max_post_size, "Sink size exceeds the maximum.");
+        return limit_exceeded(max_post_size);
+    }
+    let mut received = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => {
+                received += chunk.len();
+                if received > max_post_size {
+                    warn!(received, max_post_size, "Sink body exceeds the maximum.");
+                    return limit_exceeded(max_post_size);
+                }
+                if received > size {
+                    warn!(received, size, "Sink body is longer than its size.");
+                    return StatusCode::BAD_REQUEST.into_response();
+                }
+            }
+            Err(error) => {
+                warn!(?error, received, "Sink body was cut short.");
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Counts the bytes of the `file` part of a multipart upload as they stream in. Uploads over
+/// `max_post_size` get the same JSON error as the other POST endpoints.
+pub(crate) async fn upload_handler(
+    Path(file_size): Path<usize>,
+    State(max_post_size): State<usize>,
+    mut multipart: Multipart,
+) -> Response {
+    let mut received = 0;
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(error) if error.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                warn!(received, max_post_size, "Upload exceeds the maximum.");
+                return limit_exceeded(max_post_size);
+            }
+            Err(error) => {
+                warn!(
+                    ?error,
+                    received,
+                    expected = file_size,
+                    "Invalid multipart upload."
+                );
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        if field.name() != Some("file") {
+            continue;
+        }
+        loop {
+            match field.chunk().await {
+                Ok(Some(chunk)) => received += chunk.len(),
+                Ok(None) => break,
+                Err(error) if error.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                    warn!(received, max_post_size, "Upload exceeds the maximum.");
+                    return limit_exceeded(max_post_size);
+                }
+                Err(error) => {
+                    warn!(
+                        ?error,
+                        received,
+                        expected = file_size,
+                        "Upload was cut short."
+                    );
+                    return StatusCode::BAD_REQUEST.into_response();
+                }
+            }
+        }
+    }
+    if received == file_size {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        warn!(received, expected = file_size, "Upload size mismatch.");
+        StatusCode::BAD_REQUEST.into_response()
+    }
+}
+
+/// Result of comparing a POST body's length against the expected size.
+#[derive(Serialize)]
+pub(crate) struct PostInfo {
+    received: usize,
+    expected: usize,
+    #[serde(rename = "match")]
+    matches: bool,
+}
+
+pub(crate) async fn post_info_handler(
+    Path(file_size): Path<usize>,
+    State(max_post_size): State<usize>,
+    body: Result<body::Bytes, BytesRejection>,
 ) -> impl IntoResponse {
-    if file_size == body.len() {
-        StatusCode::NO_CONTENT
+    let body = match body {
+        Ok(body) => body,
+        Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            return limit_exceeded(max_post_size);
+        }
+        Err(rejection) => return rejection.into_response(),
+    };
+    let info = PostInfo {
+        received: body.len(),
+        expected: file_size,
+        matches: body.len() == file_size,
+    };
+    let status = if info.matches {
+        StatusCode::OK
     } else {
         StatusCode::BAD_REQUEST
+    };
+    (status, Json(info)).into_response()
+}
+
+/* Connection dropping */
+
+/// Response extension that makes the service drop the connection instead of responding.
+#[derive(Clone)]
+pub(crate) struct KillConnection;
+
+/// Request extension to drop the SSH session that the request came through.
+#[derive(Clone)]
+pub(crate) struct KillTunnel(pub(crate) Arc<Notify>);
+
+/// Query parameters for the kill endpoint.
+#[derive(Deserialize)]
+pub(crate) struct KillParams {
+    /// Whether to also drop the SSH session, which then reconnects.
+    #[serde(default)]
+    tunnel: bool,
+}
+
+/// Abruptly closes the connection that the request came through, and optionally its tunnel, to
+/// exercise reconnects. Only that tunnel is dropped when running several.
+pub(crate) async fn kill_handler(
+    Query(KillParams { tunnel }): Query<KillParams>,
+    kill_tunnel: Option<Extension<KillTunnel>>,
+) -> Response {
+    if tunnel {
+        match kill_tunnel {
+            Some(Extension(KillTunnel(kill_tunnel))) => {
+                warn!("Dropping the tunnel on request.");
+                kill_tunnel.notify_one();
+            }
+            None => warn!("Dropping the connection on request, which isn't through a tunnel."),
+        }
+    } else {
+        warn!("Dropping the connection on request.");
     }
+    let mut response = StatusCode::OK.into_response();
+    response.extensions_mut().insert(KillConnection);
+    response
 }
 
 /* WebSocket handling */
 
-pub(crate) async fn ws_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
+pub(crate) async fn ws_handler(
+    State(gauge): State<Arc<ActiveGauge>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
     ws.on_upgrade(|mut socket| async move {
+        let _guard = gauge.websocket();
         loop {
             match socket.next().await {
                 Some(Ok(message)) => {
@@ -53,3 +647,17 @@ pub(crate) async fn ws_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
         }
     })
 }
+
+/* Server-Sent Events handling */
+
+pub(crate) async fn sse_handler(
+    State(interval): State<Duration>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Each event carries a sequence number, so that clients can detect dropped events.
+    let events = stream::unfold(0u64, move |id| async move {
+        tokio::time::sleep(interval).await;
+        let event = Event::default().id(id.to_string()).data(id.to_string());
+        Some((Ok(event), id + 1))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
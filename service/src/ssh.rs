@@ -1,38 +1,90 @@
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
 
 use color_eyre::{Result, eyre::WrapErr, eyre::eyre};
-use hyper_util::{
-    rt::{TokioExecutor, TokioIo},
-    server::conn::auto::Builder,
-};
+use futures::future::join_all;
 use russh::{
-    Channel, ChannelId, ChannelMsg, Disconnect,
-    client::{self, Config, Handle, Msg, Session, connect_stream},
+    Channel, ChannelId, ChannelMsg, Disconnect, MethodKind,
+    client::{
+        self, AuthResult, Config, Handle, KeyboardInteractiveAuthResponse, Msg, Session,
+        connect_stream,
+    },
     keys::{HashAlg, PrivateKey, PrivateKeyWithHashAlg, ssh_key},
 };
-use tokio::io::{AsyncWriteExt, stderr, stdout};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, stderr, stdout},
+    net::{TcpSocket, TcpStream},
+};
 use tracing::{debug, info, instrument, trace, warn};
 
-use crate::RouterService;
+use crate::{ConnectionError, ConnectionKilled, HttpConfig, IpFamily, PrimeConfig, RouterService};
 
 /* Russh session and client */
 
 /// User-implemented session type as a helper for interfacing with the SSH protocol.
 pub(crate) struct TcpForwardSession(Handle<Client>);
 
+/// Where and as whom to connect to the SSH server.
+pub(crate) struct SshConnectOptions<'a> {
+    pub(crate) host: &'a str,
+    pub(crate) port: u16,
+    pub(crate) ip_family: IpFamily,
+    /// Local IP address to bind the connection to, to pick the network interface.
+    pub(crate) local_address: Option<IpAddr>,
+    pub(crate) login_name: &'a str,
+    /// Responses to the keyboard-interactive prompts, in order, for servers that require it after
+    /// the key.
+    pub(crate) keyboard_interactive: &'a [String],
+}
+
 /// User-implemented session type as a helper for interfacing with the SSH protocol.
 impl TcpForwardSession {
-    #[instrument(level = "debug")]
+    // The keyboard-interactive responses are secrets, so only the destination is recorded.
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(host = options.host, port = options.port, login_name = options.login_name)
+    )]
     pub(crate) async fn connect_key(
-        host: &str,
-        port: u16,
-        login_name: &str,
+        options: &SshConnectOptions<'_>,
         key: Arc<PrivateKey>,
         config: Arc<Config>,
-        client_service: RouterService,
+        client_service: Option<RouterService>,
+        forwarded_hostnames: Arc<[String]>,
+        http: HttpConfig,
+        failed_connections: Arc<AtomicUsize>,
     ) -> Result<Self> {
+        let &SshConnectOptions {
+            host,
+            port,
+            ip_family,
+            local_address,
+            login_name,
+            keyboard_interactive,
+        } = options;
         debug!("TcpForwardSession connecting...");
-        let socket = tokio::net::TcpStream::connect((host, port)).await?;
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .wrap_err_with(|| format!("Unable to resolve {host}."))?
+            .filter(|addr| ip_family.matches(addr))
+            // A socket bound to a local address can only reach the same address family.
+            .filter(|addr| local_address.is_none_or(|local| local.is_ipv4() == addr.is_ipv4()))
+            .collect();
+        if addrs.is_empty() {
+            return Err(eyre!("No {ip_family} addresses found for {host}."));
+        }
+        let socket = match local_address {
+            Some(local_address) => connect_from(local_address, &addrs).await?,
+            None => TcpStream::connect(&addrs[..]).await?,
+        };
+        info!(remote = %socket.peer_addr()?, "Connected to SSH server.");
         if let Err(err) = socket.set_nodelay(true) {
             debug!("Failed to set nodelay: {err}");
         }
@@ -42,12 +94,15 @@ impl TcpForwardSession {
             Client {
                 server_fingerprint: None,
                 service: client_service,
+                forwarded_hostnames,
+                http,
+                failed_connections,
             },
         )
         .await
         {
             Ok(mut session) => {
-                if session
+                let result = session
                     .authenticate_publickey(
                         login_name,
                         PrivateKeyWithHashAlg::new(
@@ -60,13 +115,42 @@ impl TcpForwardSession {
                         ),
                     )
                     .await
-                    .wrap_err_with(|| "Error while authenticating with key.")?
-                    .success()
-                {
-                    debug!("Key authentication succeeded!");
-                    Ok(Self(session))
-                } else {
-                    Err(eyre!("Key authentication failed."))
+                    .wrap_err_with(|| "Error while authenticating with key.")?;
+                match result {
+                    AuthResult::Success => {
+                        debug!("Key authentication succeeded!");
+                        Ok(Self(session))
+                    }
+                    // The key was accepted, but the server wants another method on top of it.
+                    AuthResult::Failure {
+                        remaining_methods,
+                        partial_success: true,
+                    } => {
+                        info!(
+                            ?remaining_methods,
+                            "Key accepted, but the server requires further authentication."
+                        );
+                        if keyboard_interactive.is_empty()
+                            || !remaining_methods.contains(&MethodKind::KeyboardInteractive)
+                        {
+                            return Err(eyre!(
+                                "Server also requires one of {remaining_methods:?}."
+                            ));
+                        }
+                        authenticate_keyboard_interactive(
+                            &mut session,
+                            login_name,
+                            keyboard_interactive,
+                        )
+                        .await?;
+                        Ok(Self(session))
+                    }
+                    AuthResult::Failure {
+                        remaining_methods, ..
+                    } => {
+                        debug!(?remaining_methods, "Key rejected.");
+                        Err(eyre!("Key authentication failed."))
+                    }
                 }
             }
             Err(err) => Err(err).wrap_err_with(|| "Unable to connect to remote host."),
@@ -75,8 +159,19 @@ impl TcpForwardSession {
 
     /// Sends a port forwarding request and opens a session to receive miscellaneous data.
     /// The function yields when the session is broken (for example, if the connection was lost).
+    ///
+    /// A forwarding is requested for each of the hostnames over the session. Once the server
+    /// announces each assigned hostname, the `prime` connections are opened back through it in the
+    /// background, and its URL is printed on its own line if `print_public_url` is set.
     #[instrument(level = "debug", skip(self))]
-    pub(crate) async fn start_forwarding(&mut self, exec: Option<&str>) -> Result<u32> {
+    pub(crate) async fn start_forwarding(
+        &mut self,
+        exec: Option<&str>,
+        hostnames: &[String],
+        prime: &PrimeConfig,
+        print_public_url: bool,
+    ) -> Result<u32> {
+        let started = Instant::now();
         let session = &mut self.0;
         let mut channel = session
             .channel_open_session()
@@ -89,11 +184,18 @@ impl TcpForwardSession {
                 .wrap_err_with(|| "exec error.")?;
         }
         debug!("Created open session channel.");
-        session
-            .tcpip_forward("measure", 80)
-            .await
-            .wrap_err_with(|| "tcpip_forward error.")?;
-        debug!("Requested tcpip_forward session.");
+        for hostname in hostnames {
+            session
+                .tcpip_forward(hostname, 80)
+                .await
+                .wrap_err_with(|| format!("Server rejected forwarding for {hostname}."))?;
+        }
+        info!(
+            forwardings = hostnames.len(),
+            elapsed = ?started.elapsed(),
+            "Forwardings accepted."
+        );
+        let mut assigned_hostnames: Vec<String> = Vec::with_capacity(hostnames.len());
         // let mut stdin = stdin();
         let mut stdout = stdout();
         let mut stderr = stderr();
@@ -105,6 +207,39 @@ impl TcpForwardSession {
             match msg {
                 ChannelMsg::Data { ref data } => {
                     stdout.write_all(data).await?;
+                    // Each forwarding's URL is announced on a line of its own.
+                    let output: &[u8] = data;
+                    for line in output.split(|byte| *byte == b'\n') {
+                        if assigned_hostnames.len() == hostnames.len() {
+                            break;
+                        }
+                        let Some(public_url) = find_public_url(line) else {
+                            continue;
+                        };
+                        let hostname = url_hostname(&public_url).to_string();
+                        if assigned_hostnames.contains(&hostname) {
+                            continue;
+                        }
+                        info!(
+                            requested = ?hostnames,
+                            assigned = %hostname,
+                            %public_url,
+                            "Forwarding assigned."
+                        );
+                        if print_public_url {
+                            stdout
+                                .write_all(format!("{public_url}\n").as_bytes())
+                                .await?;
+                        }
+                        if prime.connections > 0 {
+                            tokio::spawn(prime_tunnel(
+                                public_url.clone(),
+                                hostname.clone(),
+                                prime.clone(),
+                            ));
+                        }
+                        assigned_hostnames.push(hostname);
+                    }
                     stdout.flush().await?;
                 }
                 ChannelMsg::ExtendedData { ref data, ext: 1 } => {
@@ -135,10 +270,215 @@ impl TcpForwardSession {
     }
 }
 
+/// Answers the server's keyboard-interactive prompts with the given responses, in order.
+async fn authenticate_keyboard_interactive(
+    session: &mut Handle<Client>,
+    login_name: &str,
+    responses: &[String],
+) -> Result<()> {
+    let mut responses = responses.iter();
+    let mut response = session
+        .authenticate_keyboard_interactive_start(login_name, None::<String>)
+        .await
+        .wrap_err_with(|| "Error while starting keyboard-interactive authentication.")?;
+    loop {
+        match response {
+            KeyboardInteractiveAuthResponse::Success => {
+                debug!("Keyboard-interactive authentication succeeded!");
+                return Ok(());
+            }
+            KeyboardInteractiveAuthResponse::Failure {
+                remaining_methods, ..
+            } => {
+                return Err(eyre!(
+                    "Keyboard-interactive authentication failed, expected {remaining_methods:?}."
+                ));
+            }
+            KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } => {
+                debug!(
+                    prompts = prompts.len(),
+                    "Answering keyboard-interactive prompts."
+                );
+                let answers = prompts
+                    .iter()
+                    .map(|_| {
+                        responses
+                            .next()
+                            .cloned()
+                            .ok_or_else(|| eyre!("Not enough keyboard-interactive responses."))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                response = session
+                    .authenticate_keyboard_interactive_respond(answers)
+                    .await
+                    .wrap_err_with(|| "Error while answering keyboard-interactive prompts.")?;
+            }
+        }
+    }
+}
+
+/// Connects to the first reachable address from a socket bound to the local address, so that the
+/// connection goes through that address's network interface.
+async fn connect_from(local_address: IpAddr, addrs: &[SocketAddr]) -> Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket
+            .bind(SocketAddr::new(local_address, 0))
+            .wrap_err_with(|| format!("Unable to bind to {local_address}."))?;
+        match socket.connect(*addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error.map_or_else(|| eyre!("No addresses to connect to."), Into::into))
+}
+
+/// Requests `/health` from the public URL over the given number of connections at once, so that
+/// the proxy opens them back through the tunnel before any real load arrives.
+async fn prime_tunnel(
+    public_url: String,
+    hostname: String,
+    PrimeConfig {
+        connections,
+        ca_cert,
+        address,
+    }: PrimeConfig,
+) {
+    let started = Instant::now();
+    let url = format!("{public_url}/health");
+    let ca_cert = match &ca_cert {
+        Some(path) => match load_certificate(path).await {
+            Ok(ca_cert) => Some(ca_cert),
+            Err(error) => {
+                warn!(?error, "Unable to prime any connections.");
+                return;
+            }
+        },
+        None => None,
+    };
+    let requests = (0..connections).map(|_| async {
+        // A client per request, so that each one opens a connection of its own.
+        let mut client = reqwest::Client::builder();
+        if let Some(ca_cert) = &ca_cert {
+            client = client.add_root_certificate(ca_cert.clone());
+        }
+        if let Some(address) = address {
+            client = client.resolve(&hostname, address);
+        }
+        client.build()?.get(&url).send().await?.error_for_status()?;
+        Ok::<_, reqwest::Error>(())
+    });
+    let mut failed = 0;
+    let mut last_error = None;
+    for result in join_all(requests).await {
+        if let Err(error) = result {
+            debug!(?error, "Priming request failed.");
+            failed += 1;
+            last_error = Some(error);
+        }
+    }
+    if failed == connections {
+        warn!(%url, error = ?last_error, "Unable to prime any connections.");
+    } else {
+        info!(
+            connections,
+            failed,
+            elapsed = ?started.elapsed(),
+            "Priming complete."
+        );
+    }
+}
+
+/// Loads a PEM root certificate to trust.
+async fn load_certificate(path: &Path) -> Result<reqwest::Certificate> {
+    let pem = tokio::fs::read(path)
+        .await
+        .wrap_err_with(|| format!("Unable to read CA certificate {}.", path.display()))?;
+    reqwest::Certificate::from_pem(&pem)
+        .wrap_err_with(|| format!("Invalid CA certificate {}.", path.display()))
+}
+
+/// Hostname to request for each of the forwardings. With several, each one gets its index appended
+/// to the first label of the bind hostname, so that the proxy routes them apart.
+pub(crate) fn forwarding_hostnames(bind_hostname: &str, forwardings: usize) -> Vec<String> {
+    if forwardings <= 1 {
+        return vec![bind_hostname.to_string()];
+    }
+    (0..forwardings)
+        .map(|index| match bind_hostname.split_once('.') {
+            Some((label, rest)) => format!("{label}-{index}.{rest}"),
+            None => format!("{bind_hostname}-{index}"),
+        })
+        .collect()
+}
+
+/// Finds the first HTTP(S) URL announced in the server's output, if any, keeping only its scheme,
+/// hostname and port.
+fn find_public_url(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data)
+        .split_whitespace()
+        .find_map(|word| {
+            let (scheme, rest) = word
+                .split_once("https://")
+                .map(|(_, rest)| ("https", rest))
+                .or_else(|| word.split_once("http://").map(|(_, rest)| ("http", rest)))?;
+            // Stop at the path, or any trailing escape sequence.
+            let authority = rest
+                .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':')))
+                .next()?
+                .trim_end_matches(':');
+            (!url_hostname(authority).is_empty()).then(|| format!("{scheme}://{authority}"))
+        })
+}
+
+/// Hostname of a URL or authority, without the scheme or port.
+fn url_hostname(url: &str) -> &str {
+    let authority = url
+        .split_once("://")
+        .map_or(url, |(_, authority)| authority);
+    authority
+        .split_once(':')
+        .map_or(authority, |(hostname, _)| hostname)
+}
+
+/// Serves a forwarded connection, counting it in `failed_connections` if it fails for any reason
+/// other than `/kill` closing it.
+async fn serve_forwarded<I>(
+    http: HttpConfig,
+    io: I,
+    service: RouterService,
+    failed_connections: Arc<AtomicUsize>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    match http.serve(io, service).await {
+        Ok(()) => {}
+        Err(ConnectionError::Failed(error)) if ConnectionKilled::caused(&*error) => {
+            debug!("Killed forwarded connection.");
+        }
+        Err(error) => {
+            let failed = failed_connections.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!(?error, failed, "Failed to serve forwarded connection.");
+        }
+    }
+}
+
 /// Our SSH client implementing the `Handler` callbacks for the functions we need to use.
 struct Client {
     server_fingerprint: Option<String>,
-    service: RouterService,
+    /// Service for forwarded connections, or `None` if no forwarding is expected.
+    service: Option<RouterService>,
+    /// Hostnames requested for the forwardings. When there are several, each forwarded
+    /// connection is counted under the tunnel index of the hostname that it's for.
+    forwarded_hostnames: Arc<[String]>,
+    http: HttpConfig,
+    /// Number of forwarded connections that failed to be served, across all sessions.
+    failed_connections: Arc<AtomicUsize>,
 }
 
 impl client::Handler for Client {
@@ -165,13 +505,26 @@ impl client::Handler for Client {
         originator_port: u32,
         session: &mut Session,
     ) -> Result<(), Self::Error> {
-        let hyper_service = self.service.clone();
-        tokio::spawn(async move {
-            Builder::new(TokioExecutor::new())
-                .serve_connection_with_upgrades(TokioIo::new(channel.into_stream()), hyper_service)
-                .await
-                .expect("Invalid request");
-        });
+        let Some(mut hyper_service) = self.service.clone() else {
+            warn!("Received unexpected forwarded connection.");
+            return Ok(());
+        };
+        if self.forwarded_hostnames.len() > 1 {
+            match self
+                .forwarded_hostnames
+                .iter()
+                .position(|hostname| hostname == connected_address)
+            {
+                Some(tunnel) => hyper_service = hyper_service.for_tunnel(tunnel),
+                None => debug!("Forwarded connection for an unknown hostname."),
+            }
+        }
+        tokio::spawn(serve_forwarded(
+            self.http,
+            channel.into_stream(),
+            hyper_service,
+            Arc::clone(&self.failed_connections),
+        ));
         Ok(())
     }
 
@@ -222,3 +575,70 @@ impl client::Handler for Client {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use tokio::io::AsyncWriteExt;
+
+    use super::{forwarding_hostnames, serve_forwarded};
+    use crate::{HttpConfig, LatencyDistribution, PayloadPattern, RouterConfig, get_router};
+
+    #[tokio::test]
+    async fn counts_garbage_forwarded_connections() {
+        let service = get_router(RouterConfig {
+            max_data_size: 1024,
+            max_post_size: 1024,
+            get_jitter_bytes: 0,
+            sse_interval: Duration::from_secs(1),
+            seed: Some(0),
+            payload_pattern: PayloadPattern::Zeros,
+            latency: LatencyDistribution::Uniform { min: 0.0, max: 0.0 },
+            access_log: false,
+            enable_flaky: false,
+            enable_kill: false,
+            tunnels: 1,
+            max_concurrent_requests: None,
+            reject_over_limit: false,
+        });
+        let failed_connections = Arc::new(AtomicUsize::new(0));
+        // Stands in for the forwarded channel's stream.
+        let (mut remote, forwarded) = tokio::io::duplex(64 * 1024);
+        let serving = tokio::spawn(serve_forwarded(
+            HttpConfig::default(),
+            forwarded,
+            service,
+            Arc::clone(&failed_connections),
+        ));
+        remote
+            .write_all(b"\x00\xff\x13garbage that isn't HTTP\r\n\r\n\x16\x03\x01")
+            .await
+            .unwrap();
+        remote.shutdown().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), serving)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(failed_connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn derives_a_hostname_for_each_forwarding() {
+        assert_eq!(forwarding_hostnames("measure", 1), ["measure"]);
+        assert_eq!(
+            forwarding_hostnames("measure", 2),
+            ["measure-0", "measure-1"]
+        );
+        assert_eq!(
+            forwarding_hostnames("bench.example.com", 2),
+            ["bench-0.example.com", "bench-1.example.com"]
+        );
+    }
+}
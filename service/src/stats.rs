@@ -0,0 +1,191 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+/* Active connections */
+
+/// Number of requests and WebSocket connections being handled at once, as seen by the service, to
+/// compare against the client's concurrency.
+#[derive(Default)]
+pub(crate) struct ActiveGauge {
+    requests: AtomicUsize,
+    websockets: AtomicUsize,
+    /// Highest number of concurrent requests since the last log.
+    peak_requests: AtomicUsize,
+    /// Highest number of concurrent WebSocket connections since the last log.
+    peak_websockets: AtomicUsize,
+}
+
+/// Keeps a request or WebSocket connection counted as active until it's dropped.
+pub(crate) struct ActiveGuard {
+    gauge: Arc<ActiveGauge>,
+    websocket: bool,
+}
+
+impl ActiveGauge {
+    pub(crate) fn request(self: &Arc<Self>) -> ActiveGuard {
+        self.enter(false)
+    }
+
+    pub(crate) fn websocket(self: &Arc<Self>) -> ActiveGuard {
+        self.enter(true)
+    }
+
+    fn counters(&self, websocket: bool) -> (&AtomicUsize, &AtomicUsize) {
+        if websocket {
+            (&self.websockets, &self.peak_websockets)
+        } else {
+            (&self.requests, &self.peak_requests)
+        }
+    }
+
+    fn enter(self: &Arc<Self>, websocket: bool) -> ActiveGuard {
+        let (active, peak) = self.counters(websocket);
+        let current = active.fetch_add(1, Ordering::Relaxed) + 1;
+        peak.fetch_max(current, Ordering::Relaxed);
+        ActiveGuard {
+            gauge: Arc::clone(self),
+            websocket,
+        }
+    }
+
+    /// Logs the current and peak counts on every interval that had any activity.
+    pub(crate) async fn log_every(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let requests = self.requests.load(Ordering::Relaxed);
+            let websockets = self.websockets.load(Ordering::Relaxed);
+            // Start the next interval's peaks from the current counts.
+            let peak_requests = self.peak_requests.swap(requests, Ordering::Relaxed);
+            let peak_websockets = self.peak_websockets.swap(websockets, Ordering::Relaxed);
+            if peak_requests > 0 || peak_websockets > 0 {
+                info!(
+                    requests,
+                    peak_requests, websockets, peak_websockets, "Active connections."
+                );
+            }
+        }
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        let (active, _) = self.gauge.counters(self.websocket);
+        active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/* Per-tunnel load */
+
+/// Number of requests served through each tunnel, to check whether the proxy spreads connections
+/// across them or pins them to one.
+pub(crate) struct TunnelCounters {
+    requests: Vec<AtomicUsize>,
+}
+
+impl TunnelCounters {
+    pub(crate) fn new(tunnels: usize) -> Self {
+        Self {
+            requests: (0..tunnels).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    pub(crate) fn record(&self, tunnel: usize) {
+        if let Some(requests) = self.requests.get(tunnel) {
+            requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn log_summary(&self) {
+        let requests: Vec<usize> = self
+            .requests
+            .iter()
+            .map(|requests| requests.load(Ordering::Relaxed))
+            .collect();
+        info!(?requests, "Requests per tunnel.");
+    }
+
+    /// Logs the running totals on every interval.
+    pub(crate) async fn log_every(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            self.log_summary();
+        }
+    }
+}
+
+/* Tunnel statistics */
+
+/// Statistics about the stability of the SSH tunnel across reconnections.
+pub(crate) struct ReconnectStats {
+    started: Instant,
+    connections: usize,
+    last_connected: Option<Instant>,
+    connected_since: Option<Instant>,
+    uptime: Duration,
+}
+
+impl ReconnectStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: 0,
+            last_connected: None,
+            connected_since: None,
+            uptime: Duration::ZERO,
+        }
+    }
+
+    /// Marks the start of a new session, logging a summary if it's a reconnection.
+    pub(crate) fn connected(&mut self) {
+        let now = Instant::now();
+        if let Some(last_connected) = self.last_connected {
+            info!(
+                reconnects = self.connections,
+                since_last_connect = humantime::format_duration(now - last_connected).to_string(),
+                uptime = humantime::format_duration(self.uptime()).to_string(),
+                elapsed = humantime::format_duration(self.started.elapsed()).to_string(),
+                "Reconnected."
+            );
+        }
+        self.connections += 1;
+        self.last_connected = Some(now);
+        self.connected_since = Some(now);
+    }
+
+    /// Marks the end of the current session.
+    pub(crate) fn disconnected(&mut self) {
+        if let Some(connected_since) = self.connected_since.take() {
+            self.uptime += connected_since.elapsed();
+        }
+    }
+
+    /// Total time spent connected, including the current session.
+    fn uptime(&self) -> Duration {
+        self.uptime
+            + self
+                .connected_since
+                .map(|connected_since| connected_since.elapsed())
+                .unwrap_or_default()
+    }
+
+    pub(crate) fn log_summary(&self) {
+        info!(
+            connections = self.connections,
+            reconnects = self.connections.saturating_sub(1),
+            uptime = humantime::format_duration(self.uptime()).to_string(),
+            elapsed = humantime::format_duration(self.started.elapsed()).to_string(),
+            "Tunnel statistics."
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use color_eyre::eyre::WrapErr;
+use hyper::HeaderMap;
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use tracing::{Subscriber, level_filters::LevelFilter, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+/// Exports spans to an OpenTelemetry collector over OTLP/HTTP, flushing them when dropped.
+pub struct Otlp {
+    provider: SdkTracerProvider,
+}
+
+impl Otlp {
+    pub fn new(endpoint: &str) -> color_eyre::Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .wrap_err_with(|| "Unable to create the OTLP exporter.")?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        Ok(Self { provider })
+    }
+
+    /// Layer exporting every span at the debug level or above, whatever the logging level is, so
+    /// that the per-request spans are always included.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer()
+            .with_tracer(self.provider.tracer(env!("CARGO_PKG_NAME")))
+            .with_filter(LevelFilter::DEBUG)
+    }
+}
+
+impl Drop for Otlp {
+    fn drop(&mut self) {
+        if let Err(error) = self.provider.shutdown() {
+            warn!(%error, "Unable to flush the OpenTelemetry spans.");
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Span for a request, continuing the client's trace if it sent a `traceparent` header.
+pub(crate) fn request_span<B>(request: &hyper::Request<B>) -> tracing::Span {
+    let span = tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+    );
+    let context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    let _ = span.set_parent(context);
+    span
+}
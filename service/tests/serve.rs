@@ -0,0 +1,71 @@
+use std::{net::SocketAddr, time::Duration};
+
+use sandhole_benchmark_service::{
+    HttpConfig, LatencyDistribution, PayloadPattern, RouterConfig, get_router, serve_direct,
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+/// Serves a small router directly on a free local port, returning once it accepts connections.
+async fn start_service(http: HttpConfig) -> SocketAddr {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let service = get_router(RouterConfig {
+        max_data_size: 1024,
+        max_post_size: 1024,
+        get_jitter_bytes: 0,
+        sse_interval: Duration::from_secs(1),
+        seed: Some(0),
+        payload_pattern: PayloadPattern::Zeros,
+        latency: LatencyDistribution::Uniform { min: 0.0, max: 0.0 },
+        access_log: false,
+        enable_flaky: false,
+        enable_kill: false,
+        tunnels: 1,
+        max_concurrent_requests: None,
+        reject_over_limit: false,
+    });
+    tokio::spawn(serve_direct(addr, service, http));
+    for _ in 0..100 {
+        if TcpStream::connect(addr).await.is_ok() {
+            return addr;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("service didn't start listening on {addr}");
+}
+
+#[tokio::test]
+async fn keeps_serving_after_garbage() {
+    let addr = start_service(HttpConfig::default()).await;
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"\x00\xff\x13garbage that isn't HTTP\r\n\r\n\x16\x03\x01")
+        .await
+        .unwrap();
+    socket.shutdown().await.unwrap();
+    drop(socket);
+    let response = reqwest::get(format!("http://{addr}/health")).await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.text().await.unwrap(), "OK");
+}
+
+#[tokio::test]
+async fn times_out_hung_requests() {
+    let timeout = Duration::from_millis(500);
+    let addr = start_service(HttpConfig {
+        connection_timeout: Some(timeout),
+        ..Default::default()
+    })
+    .await;
+    let started = std::time::Instant::now();
+    // Delayed for an hour, so it only completes through the timeout.
+    let response = reqwest::get(format!(
+        "http://{addr}/latency/16?distribution=uniform:3600000:3600000"
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert!(started.elapsed() < timeout + Duration::from_secs(1));
+}